@@ -1,18 +1,25 @@
 extern crate header;
 extern crate glob;
+extern crate host;
 
+mod fingerprint;
+mod inspect;
+mod symbols;
+
+use fingerprint::Fingerprint;
 use header::SayHelloService;
 use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fmt::Write as _;
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Read, Write as _};
+use std::io::{BufRead, BufReader, BufWriter, Write as _};
 use std::path::*;
 use std::process::{Command, Stdio};
 use std::time::SystemTime;
 use std::cell::Cell;
 use std::io::ErrorKind;
 use std::time::{Instant, Duration};
+use std::sync::OnceLock;
 
 fn exit() -> ! {
     std::process::exit(1)
@@ -51,7 +58,7 @@ const HOST: Triple = TRIPLE_WINDOWS;
 
 const DEBUG: bool = cfg!(debug_assertions);
 const RELEASE: bool = !DEBUG;
-const PROFILE: &'static str = if DEBUG {
+const PROFILE: &str = if DEBUG {
     "debug"
 } else {
     "release"
@@ -96,7 +103,7 @@ impl Toolchain {
             fn eat(t: Option<&str>, prefix: &str) -> String {
                 let t = t.unwrap_or_else(|| panic!("expected something starting with {:?}", prefix));
                 assert!(t.starts_with(prefix), "expected something starting with {:?}", prefix);
-                (&t[prefix.len()..]).to_string()
+                t[prefix.len()..].to_string()
             }
             let host = eat(host, "host:");
             let target = eat(target, "target:");
@@ -117,18 +124,26 @@ impl Toolchain {
         let cmds = self.cmds
             .get(&cfg)
             .unwrap_or_else(|| panic!("Command for doing {:?} not provided in toolchain.txt", cfg));
-        let mut cmds: Vec<String> = cmds.clone();
-        for c in &mut cmds {
-            for (k, v) in env {
-                *c = c.replace(k, &v);
+        let mut expanded: Vec<String> = vec![];
+        for c in cmds {
+            // Check the template rather than the result; values may contain a '$' of their own,
+            // eg "$ORIGIN".
+            let mut probe = c.clone();
+            for (k, _) in env {
+                probe = probe.replace(k, "");
             }
-        }
-        cmds.retain(|c| !c.is_empty());
-        for c in &cmds {
-            if c.contains('$') {
+            if probe.contains('$') {
                 panic!("{:?} has unexpanded variables", cmds);
             }
+            let mut c = c.clone();
+            for (k, v) in env {
+                c = c.replace(k, v);
+            }
+            // A value holding several arguments separates them with newlines.
+            expanded.extend(c.split('\n').map(String::from));
         }
+        let mut cmds = expanded;
+        cmds.retain(|c| !c.is_empty());
         let mut globbed = vec![];
         for c in &cmds {
             if cfg!(target_os = "windows") {
@@ -154,25 +169,55 @@ impl Toolchain {
         cmd.args(&globbed[1..]);
         cmd
     }
+    /// Whether the program that does `cmd` is there. Not all of the LLVM tools are checked in
+    /// yet (see toolchain/README.txt), so whatever needs the missing ones gets skipped.
+    fn has(&self, pair: Pair, cmd: &str) -> bool {
+        let cfg = Config {
+            host: pair.host.to_string(),
+            target: pair.target.to_string(),
+            cmd: cmd.to_string(),
+        };
+        let program = match self.cmds.get(&cfg).and_then(|c| c.first()) {
+            Some(program) => Path::new(program),
+            None => return false,
+        };
+        if program.components().count() > 1 {
+            return program.is_file();
+        }
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        std::env::split_paths(&path).any(|dir| dir.join(program).is_file())
+    }
+}
+
+/// Whether all of `cmds` can be run. If they can't, says that `what` is being skipped.
+fn have_tools(toolchain: &Toolchain, pair: Pair, cmds: &[&str], what: &str) -> bool {
+    let missing = cmds
+        .iter()
+        .filter(|cmd| !toolchain.has(pair, cmd))
+        .copied()
+        .collect::<Vec<&str>>();
+    if missing.is_empty() {
+        return true;
+    }
+    println!("warning: skipping {}: no {} (see toolchain/README.txt)", what, missing.join(" or "));
+    false
 }
 
 
 fn glob1(dir: &Path, prefix: &str, suffix: &str) -> Option<PathBuf> {
     assert!(!suffix.starts_with('.'));
     let mut found = None;
-    for entry in dir.read_dir().ok()? {
-        if let Ok(entry) = entry {
-            let entry = entry.path();
-            let is_so = entry.extension() == Some(OsStr::new(suffix));
-            let is_std = entry.file_name()
-                .and_then(|n| n.to_str())
-                .map(|n| n.starts_with(prefix)) == Some(true);
-            if is_so && is_std {
-                if found.is_some() {
-                    panic!("multiple {}*.{}'s found in {:?}", prefix, suffix, dir);
-                }
-                found = Some(entry);
+    for entry in dir.read_dir().ok()?.flatten() {
+        let entry = entry.path();
+        let is_so = entry.extension() == Some(OsStr::new(suffix));
+        let is_std = entry.file_name()
+            .and_then(|n| n.to_str())
+            .map(|n| n.starts_with(prefix)) == Some(true);
+        if is_so && is_std {
+            if found.is_some() {
+                panic!("multiple {}*.{}'s found in {:?}", prefix, suffix, dir);
             }
+            found = Some(entry);
         }
     }
     found
@@ -182,73 +227,154 @@ fn find_rust_std(toolchain: &Toolchain, pair: Pair, token_package: &str) -> Opti
     let mut stdpath = toolchain.get(pair, "cargo", &[]);
     stdpath
         .arg("--quiet")
-        .args(&["rustc", "-p", token_package]);
+        .args(["rustc", "-p", token_package]);
     if pair.foreign() {
         stdpath.arg(format!("--target={}", pair.target));
     }
     stdpath
         .arg("--")
-        .args(&["--print", "sysroot"]);
+        .args(["--print", "sysroot"]);
     let stdpath = stdpath.output().ok()?;
     let stdpath = std::str::from_utf8(&stdpath.stdout).expect("find_rust_std parse");
     if stdpath.is_empty() { return None; }
     let stdpath = stdpath.strip_suffix('\n').expect("strip");
     let stdpath = format!("{}/lib/", stdpath);
     let mut stdpath: PathBuf = stdpath.into();
-    if pair.host == TRIPLE_WINDOWS || pair.target == TRIPLE_WINDOWS {
-        // lib/rustlib/x86_64-pc-windows-msvc/lib/std-3d786a338e3fbd3c.dll.lib
-        // (Windows uses the same structure)
-        stdpath.push("rustlib");
-        stdpath.push(pair.target);
-        stdpath.push("lib");
-    }
-    match pair.target {
-        TRIPLE_LINUX => glob1(&stdpath, "libstd-", "so"),
-        TRIPLE_WINDOWS => glob1(&stdpath, "std-", "dll"),
+    let find = |stdpath: &Path| match pair.target {
+        TRIPLE_LINUX => glob1(stdpath, "libstd-", "so"),
+        TRIPLE_WINDOWS => glob1(stdpath, "std-", "dll"),
         e => todo!("find_rust_std {:?}", e),
+    };
+    if pair.host != TRIPLE_WINDOWS && pair.target != TRIPLE_WINDOWS {
+        if let Some(std) = find(&stdpath) {
+            return Some(std);
+        }
     }
+    // lib/rustlib/x86_64-pc-windows-msvc/lib/std-3d786a338e3fbd3c.dll.lib
+    // (Windows uses the same structure, and newer toolchains only put libstd.so here.)
+    stdpath.push("rustlib");
+    stdpath.push(pair.target);
+    stdpath.push("lib");
+    find(&stdpath)
+}
+
+/// Where the running executable is. Relative entries in the search path are relative to it,
+/// not to wherever it was run from.
+fn exe_dir() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+        .unwrap_or_else(|| ".".into())
 }
 
-fn seek(path: String) -> Option<PathBuf> {
-    let mut it = glob::glob(&path).unwrap();
-    let f = it.next();
-    if let Some(g) = it.next() {
-        panic!("{:?} matched multiple files, including {:?} and {:?}", path, f, g);
+/// Where to look for dylibs: `PLUGINS3_PATH`, then the lines of `search-path.txt` beside the
+/// executable, then beside the executable itself. `{package}` is replaced with the package's
+/// name, so `{package}/lib` works.
+fn search_path(package: &str) -> Vec<PathBuf> {
+    let base = exe_dir();
+    let mut entries: Vec<PathBuf> = vec![];
+    if let Some(env) = std::env::var_os("PLUGINS3_PATH") {
+        entries.extend(std::env::split_paths(&env));
+    }
+    if let Ok(text) = std::fs::read_to_string(base.join("search-path.txt")) {
+        for line in text.lines() {
+            let line = line.trim();
+            if line.starts_with('#') || line.is_empty() { continue; }
+            entries.push(line.into());
+        }
+    }
+    entries.extend([".".into(), "lib".into(), format!("{}/lib", package).into()]);
+    let mut ret: Vec<PathBuf> = vec![];
+    for entry in entries {
+        let entry = entry.to_string_lossy().replace("{package}", package);
+        let dir: PathBuf = base.join(entry).components().collect();
+        if !ret.contains(&dir) {
+            ret.push(dir);
+        }
     }
-    f.map(Result::unwrap)
+    ret
+}
+
+/// `1.10.0` sorts after `1.9.0`. Anything after a `-` is ignored.
+fn version_key(version: &str) -> Vec<u64> {
+    let version = version.split('-').next().unwrap_or("");
+    version.split('.').map(|n| n.parse().unwrap_or(0)).collect()
 }
 
-// fn findlib
+/// Finds a package's dylib in the search path. If there's more than one, the ones with the
+/// wrong fingerprint are out, then the lower versions, then the older files; each one that
+/// loses gets a note saying why.
 fn seek_lib(pair: Pair, package: &str) -> Result<PathBuf, String> {
-    let mut hay = String::new();
-    macro_rules! seek {
-        ($($tt:tt)*) => {
-            let path = format!($($tt)*);
-            write!(hay, "\n  {}", path).ok();
-            if let Some(path) = seek(path) {
-                return Ok(path);
-            }
+    let libname = pair.libname(package);
+    let dirs = search_path(package);
+    let mut candidates: Vec<PathBuf> = vec![];
+    for dir in &dirs {
+        let path = dir.join(&libname);
+        if !path.is_file() { continue; }
+        let real = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if candidates.iter().all(|c| c.canonicalize().unwrap_or_else(|_| c.clone()) != real) {
+            candidates.push(path);
+        }
+    }
+    if candidates.is_empty() {
+        let mut hay = String::new();
+        for dir in &dirs {
+            write!(hay, "\n  {}", dir.display()).ok();
+        }
+        return Err(format!("Unable to find {:?}; searched in:{}\n", libname, hay));
+    }
+    let mut rejected: Vec<(PathBuf, String)> = vec![];
+    if candidates.len() > 1 {
+        let (good, bad): (Vec<_>, Vec<_>) = candidates
+            .into_iter()
+            .map(|c| {
+                let fp = check_fingerprint(&c);
+                (c, fp)
+            })
+            .partition(|(_, fp)| fp.is_ok());
+        for (c, fp) in bad {
+            rejected.push((c, fp.unwrap_err().replace('\n', "\n    ")));
+        }
+        candidates = good.into_iter().map(|(c, _)| c).collect();
+    }
+    if candidates.len() > 1 {
+        let version = |c: &Path| {
+            host::Manifest::read(c)
+                .ok()
+                .flatten()
+                .map(|m| m.version)
+                .unwrap_or_default()
         };
+        let best = candidates.iter().map(|c| version_key(&version(c))).max().unwrap_or_default();
+        candidates.retain(|c| {
+            let v = version(c);
+            if version_key(&v) == best { return true; }
+            rejected.push((c.clone(), format!("it's version {}; there's a newer one", v)));
+            false
+        });
     }
-    let libname = pair.libname(package);
-    seek!("./target/{}/{}/{}", pair.target, PROFILE, libname);
-    seek!("./target/{}/{}", PROFILE, libname);
-    seek!("./lib/{}", libname);
-    seek!("./{}/lib/{}", package, libname);
-    seek!("./{}", libname);
-    Err(format!("Unable to find {:?}; searched in:{}\n", libname, hay))
+    if candidates.len() > 1 {
+        let mtime = |c: &Path| c.metadata().and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH);
+        candidates.sort_by_key(|c| std::cmp::Reverse(mtime(c)));
+        for c in candidates.drain(1..) {
+            rejected.push((c, "there's a more recently built one".into()));
+        }
+    }
+    let chosen = candidates.pop();
+    for (c, why) in &rejected {
+        println!("note: not using {}: {}", c.display(), why);
+    }
+    chosen.ok_or_else(|| format!("None of the {:?} found can be used", libname))
 }
 
 fn walk(dir: &Path, each: &mut impl FnMut(&Path)) {
     if let Ok(dir) = dir.read_dir() {
-        for entry in dir {
-            if let Ok(entry) = entry {
-                let entry = entry.path();
-                if entry.is_dir() {
-                    walk(&entry, each);
-                } else {
-                    each(&entry);
-                }
+        for entry in dir.flatten() {
+            let entry = entry.path();
+            if entry.is_dir() {
+                walk(&entry, each);
+            } else {
+                each(&entry);
             }
         }
     }
@@ -260,17 +386,14 @@ static CRT_ENV: &str = "LIB_CRT";
 static WDK_URL: &str = "https://docs.microsoft.com/en-us/legal/windows/hardware/enterprise-wdk-license-2015";
 
 fn find_crt() -> PathBuf {
-    static mut FOUND: Option<PathBuf> = None;
-    let found = unsafe { FOUND.clone() };
-    if let Some(found) = found {
-        return found;
-    }
-    let r = find_crt0();
-    if !r.exists() {
-        panic!("{} doesn't exist at {:?}", CRT, r);
-    }
-    unsafe { FOUND = Some(r.clone()); }
-    r
+    static FOUND: OnceLock<PathBuf> = OnceLock::new();
+    FOUND.get_or_init(|| {
+        let r = find_crt0();
+        if !r.exists() {
+            panic!("{} doesn't exist at {:?}", CRT, r);
+        }
+        r
+    }).clone()
 }
 fn find_crt0() -> PathBuf {
     if let Some(e) = std::env::var_os(CRT_ENV) {
@@ -416,7 +539,7 @@ fn dirty(
         return true;
     };
     let mut any = false;
-    for g in glob::glob(&inputs).expect("bad glob string") {
+    for g in glob::glob(inputs).expect("bad glob string") {
         any = true;
         let g = g.expect("expand glob");
         if let Some(g) = modified(&g) {
@@ -440,13 +563,73 @@ struct Lib {
     dependencies: &'static [&'static str],
 }
 
-fn compile_dylib(
+/// Everything that gets built into a dylib, in dependency order.
+const LIBS: &[Lib] = &[
+    Lib {
+        name: "header",
+        has_exports: true,
+        dependencies: &[],
+    },
+    Lib {
+        name: "plugin",
+        has_exports: false,
+        dependencies: &["header"],
+    },
+];
+
+/// Whether symbols from this crate are part of an interface shared by the host and plugins.
+fn is_interface(krate: &str) -> bool {
+    matches!(krate, "std" | "core" | "alloc")
+        || LIBS.iter().any(|lib| lib.has_exports && lib.name == krate)
+}
+
+/// What to do when a check finds a problem.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Policy {
+    Allow,
+    Warn,
+    Deny,
+}
+impl Policy {
+    /// Reads `--{name}=allow|warn|deny`.
+    fn from_args(name: &str, default: Policy) -> Policy {
+        let prefix = format!("--{}=", name);
+        let arg = match std::env::args().find(|a| a.starts_with(&prefix)) {
+            Some(arg) => arg,
+            None => return default,
+        };
+        match &arg[prefix.len()..] {
+            "allow" => Policy::Allow,
+            "warn" => Policy::Warn,
+            "deny" => Policy::Deny,
+            e => {
+                println!("{}: expected allow, warn, or deny; not {:?}", prefix, e);
+                exit()
+            },
+        }
+    }
+    fn enforce(self, r: Result<(), String>) {
+        let m = match r {
+            Ok(()) => return,
+            Err(m) => m,
+        };
+        match self {
+            Policy::Allow => (),
+            Policy::Warn => println!("warning: {}", m),
+            Policy::Deny => {
+                println!("{}", m);
+                exit()
+            },
+        }
+    }
+}
+
+/// Has cargo build the lib's bitcode, and gives where it put it.
+fn emit_bitcode(
     toolchain: &Toolchain,
     pair: Pair,
-    package: Lib,
-) -> PathBuf {
-    let std = find_rust_std(toolchain, pair, package.name).expect("failed to find rust std");
-    let std = std.to_str().expect("bad utf8 in std path");
+    package: &Lib,
+) -> String {
     // Assumes the command only modifies the .o files if the source hasn't changed.
     let mut cmd = toolchain.get(pair, "cargo", &[]);
     cmd.arg("rustc");
@@ -465,13 +648,75 @@ fn compile_dylib(
     //let start = Instant::now();
     assert!(cmd.status().unwrap().success());
 
+    // `<name>.bc` for a dylib crate, `<name>-<hash>.bc` otherwise. Building with other flags
+    // (another profile, say) leaves one with another hash behind, which would be duplicate
+    // symbols; this build's is the newest.
+    let emitted = glob::glob(&format!("{}/deps/{}*.bc", pair.target(), package.name))
+        .expect("bad glob string")
+        .map(|g| g.expect("expand glob"))
+        .filter(|g| {
+            let file = g.file_name().and_then(|f| f.to_str()).unwrap_or_default();
+            let rest = file.strip_prefix(package.name).and_then(|f| f.strip_suffix(".bc"));
+            rest == Some("") || rest.and_then(|r| r.strip_prefix('-')).is_some_and(|hash| !hash.contains('.'))
+        })
+        .max_by_key(|g| modified(g))
+        .unwrap_or_else(|| panic!("cargo didn't emit any bitcode for {}", package.name));
+    emitted.to_str().expect("bad utf8 in object path").to_string()
+}
+
+/// Links the bitcode from `emit_bitcode` into the dylib.
+fn compile_dylib(
+    toolchain: &Toolchain,
+    pair: Pair,
+    package: &Lib,
+    std: &Path,
+    metadata: &serde_json::Value,
+    emitted: String,
+) -> PathBuf {
+    let std = std.to_str().expect("bad utf8 in std path");
     let libname = pair.libname(package.name);
-    let objects = format!("{}/deps/{}-*.bc", pair.target(), package.name);
-    if !dirty(&objects, &libname) {
+    // cargo puts its own build of a dylib crate (header) there, newer than the bitcode, so
+    // check that it's really ours.
+    let lib_out = format!("{}/{}", pair.target(), libname);
+    let ours = matches!(symbols::read_cstr(Path::new(&lib_out), fingerprint::SYMBOL), Ok(Some(_)));
+    if ours && !dirty(&emitted, &lib_out) {
         //println!("     Elapsed {:?} (clean)", start.elapsed());
-        return libname.into();
+        return lib_out.into();
+    }
+    if ours {
+        inspect::record_previous(Path::new(&lib_out));
     }
     let mut env = vec![];
+    let mut objects = vec![emitted];
+    objects.push(stamp_bc(toolchain, pair, package, Path::new(std), metadata));
+    let merge = std::env::args().any(|a| a == "--merge-bc")
+        && have_tools(toolchain, pair, &["llvm-link", "opt"], "--merge-bc");
+    if merge {
+        // One module for the whole lib, so that the optimizer can see all of it at once.
+        let out = format!("{}/deps/{}.merged.bc", pair.target(), package.name);
+        run(toolchain.get(pair, "llvm-link", &[
+            ("$INPUTS", objects.join("\n")),
+            ("$OUT", out.clone()),
+        ]), "llvm-link");
+        // Before stripping, while the interface's inline functions still have their bodies: the
+        // pipeline gets to decide whether to inline them. What's left gets stripped after.
+        let passes = std::env::args()
+            .find_map(|a| a.strip_prefix("--passes=").map(String::from))
+            .unwrap_or_else(|| if RELEASE { "default<O2>" } else { "default<O0>" }.into());
+        let opt = format!("{}/deps/{}.opt.bc", pair.target(), package.name);
+        run(toolchain.get(pair, "opt", &[
+            ("$PASSES", passes),
+            ("$INPUT", out),
+            ("$OUT", opt.clone()),
+        ]), "opt");
+        objects = vec![opt];
+    }
+    // Without these, the dylib keeps its copies, and check_bloat only complains about them.
+    let stripped = have_tools(toolchain, pair, &["llvm-dis", "llvm-extract"], "stripping interface code from the bitcode");
+    if stripped {
+        objects = strip_interface(toolchain, pair, package, Path::new(std), objects);
+    }
+    let objects = objects.join("\n");
 
     let dll_export: String;
     let target_out = pair.target();
@@ -510,7 +755,7 @@ fn compile_dylib(
             let mut parsed = vec![];
             for word in line.split(splitter) {
                 let delim = delim.get();
-                if word == "" && delim == ' ' { continue; }
+                if word.is_empty() && delim == ' ' { continue; }
                 parsed.push((delim, word));
             }
             let mut iter = parsed.iter();
@@ -540,25 +785,31 @@ fn compile_dylib(
             exit();
         }
         linkage_names.flush().expect("flush linkage_names");
-        {linkage_names};
+        drop(linkage_names);
         env.push(("$EXPORTS_LIST", format!("@{}", dll_export)));
     }
-    let lib_out = format!("{}/{}", target_out, libname);
     env.push(("$STD", std.into()));
     env.push(("$OUT", lib_out.clone()));
     env.push(("$INPUT_OBJ", objects));
     {
         let mut lib_deps = String::new();
-        let mut first = true;
+        let mut so_deps = format!("-L{}", pair.target());
         for lib in package.dependencies {
-            if first {
-                first = false;
-            } else {
-                write!(lib_deps, " ").unwrap();
+            if !lib_deps.is_empty() {
+                writeln!(lib_deps).unwrap();
             }
             write!(lib_deps, "/defaultlib:{}/{}.lib", pair.target(), lib).unwrap();
+            write!(so_deps, "\n-l{}", lib).unwrap();
         }
         env.push(("$DLL_LIB_DEPENDENCIES", lib_deps));
+        // These give the .so DT_NEEDED entries for std & the libs it uses, so that it can be loaded
+        // without the host having to open those first.
+        env.push(("$SO_LIB_DEPENDENCIES", so_deps));
+        env.push(("$SONAME", libname.clone()));
+        env.push(("$RUNPATH", "$ORIGIN:$ORIGIN/lib".into()));
+    }
+    if pair.target == TRIPLE_LINUX {
+        install_std(pair, Path::new(std));
     }
     if pair.target == TRIPLE_WINDOWS {
         let lib: String = find_crt().into_os_string().into_string().unwrap();
@@ -593,23 +844,476 @@ fn compile_dylib(
         },
     }
     let lib_out: PathBuf = lib_out.into();
-    if package.name != "header" {
-        assert_clean(&lib_out);
+    if pair.target == TRIPLE_LINUX {
+        // (Windows checks this while linking.)
+        unwrap(check_undefined(pair, package, &lib_out, Path::new(std)));
     }
+    unwrap(check_interface_copies(pair, package, &lib_out));
+    Policy::from_args("generic-copies", Policy::Warn).enforce(check_generic_copies(package, &lib_out));
+    let bloat = Policy::from_args("bloat", Policy::Deny);
+    unwrap(check_bloat(pair, package, &lib_out, Path::new(std), bloat, stripped));
     //println!("     Elapsed {:?}", start.elapsed());
     lib_out
 }
 
-fn assert_clean(plugin: &Path) {
-    let mut buf = vec![];
-    let mut plugin = std::fs::File::open(plugin)
-        .unwrap_or_else(|e| panic!("unable to assert_clean() {:?}: {}", plugin, e));
-    plugin.read_to_end(&mut buf).expect("read failed");
-    let buf = String::from_utf8_lossy(&buf);
-    let mut bad = format!("{}_{}", "forbid", "me");
-    // If "FORBID_ME" occurs in libplugin.so, the full contents are being linked in.
-    bad.make_ascii_uppercase();
-    assert!(!buf.contains(&bad), "libplugin.so contains the fobidden test string");
+/// What `cargo metadata` says about the workspace. Once per build; every dylib's manifest
+/// comes out of it.
+fn cargo_metadata(toolchain: &Toolchain, pair: Pair) -> serde_json::Value {
+    let mut cargo = toolchain.get(pair, "cargo", &[]);
+    cargo.args(["metadata", "--format-version=1", "--no-deps"]);
+    let out = cargo.output().unwrap_or_else(|e| panic!("unable to run {:?}: {}", cargo, e));
+    if !out.status.success() {
+        println!("aborting due to failure of cargo metadata");
+        println!("  {:?}", cargo);
+        exit();
+    }
+    serde_json::from_slice(&out.stdout).expect("bad json from cargo metadata")
+}
+
+/// The dylib's manifest, from `cargo_metadata`.
+fn manifest(metadata: &serde_json::Value, package: &Lib) -> host::Manifest {
+    let meta = metadata["packages"]
+        .as_array()
+        .and_then(|packages| packages.iter().find(|p| p["name"] == package.name))
+        .unwrap_or_else(|| panic!("cargo metadata doesn't know {:?}", package.name));
+    let text = |v: &serde_json::Value| v.as_str().unwrap_or("").to_string();
+    let mut dependencies = vec![];
+    for dep in meta["dependencies"].as_array().into_iter().flatten() {
+        // Only the ones that are dylibs too; the rest are linked in.
+        let name = text(&dep["name"]);
+        if dep["kind"].is_null() && LIBS.iter().any(|l| l.name == name) {
+            dependencies.push(name);
+        }
+    }
+    host::Manifest {
+        name: text(&meta["name"]),
+        version: text(&meta["version"]),
+        description: text(&meta["description"]),
+        dependencies,
+        entry: header::ENTRY_VERSION,
+        interface: package.has_exports,
+    }
+}
+
+/// Compiles the dylib's fingerprint (see fingerprint.rs) and manifest into a bitcode file to
+/// link in.
+fn stamp_bc(toolchain: &Toolchain, pair: Pair, package: &Lib, std: &Path, metadata: &serde_json::Value) -> String {
+    let fp = Fingerprint {
+        rustc: fingerprint::rustc_version(toolchain.get(pair, "rustc", &[])),
+        std: fingerprint::std_hash(std).unwrap_or_else(|| panic!("no hash in the name of {:?}", std)),
+        header: fingerprint::source_hash(Path::new("."), fingerprint::HEADER_SOURCES),
+        profile: PROFILE.to_string(),
+    };
+    let mut fp = fp.encode().into_bytes();
+    fp.push(0);
+    let mut manifest = manifest(metadata, package).encode().into_bytes();
+    manifest.push(0);
+    let src = format!(
+        "#[no_mangle]\npub static {}: [u8; {}] = {:?};\n\
+        #[used]\n#[link_section = {:?}]\nstatic MANIFEST: [u8; {}] = {:?};\n",
+        fingerprint::SYMBOL,
+        fp.len(),
+        fp,
+        host::MANIFEST_SECTION,
+        manifest.len(),
+        manifest,
+    );
+    let rs = format!("{}/deps/stamp-{}.rs", pair.target(), package.name);
+    let bc = format!("{}/deps/stamp-{}.bc", pair.target(), package.name);
+    if std::fs::read_to_string(&rs).ok().as_ref() == Some(&src) && Path::new(&bc).exists() {
+        return bc;
+    }
+    std::fs::write(&rs, &src).unwrap_or_else(|e| panic!("unable to write {:?}: {}", rs, e));
+    let mut rustc = toolchain.get(pair, "rustc", &[]);
+    rustc.args(["--crate-type=lib", "--emit=llvm-bc", "-o", &bc, &rs]);
+    rustc.arg(format!("--crate-name=stamp_{}", package.name));
+    rustc.arg(format!("--target={}", pair.target));
+    run(rustc, "rustc");
+    bc
+}
+
+/// Runs one of the LLVM tools, giving up if it fails.
+fn run(mut cmd: Command, what: &str) {
+    match cmd.status() {
+        Ok(s) if s.success() => (),
+        r => {
+            println!("aborting due to failure of {}: {:?}", what, r);
+            println!("  {:?}", cmd);
+            exit();
+        },
+    }
+}
+
+/// The definitions in an llvm-dis listing: `(name, is_function)`.
+fn bitcode_definitions(ir: impl BufRead) -> Vec<(String, bool)> {
+    fn name(s: &str) -> Option<&str> {
+        let s = s.strip_prefix('@')?;
+        if let Some(s) = s.strip_prefix('"') {
+            return s.find('"').map(|end| &s[..end]);
+        }
+        s.find(|c: char| !(c.is_alphanumeric() || "$._-".contains(c))).map(|end| &s[..end])
+    }
+    let mut ret = vec![];
+    for line in ir.lines() {
+        let line = line.expect("reading output of llvm-dis failed");
+        if line.starts_with("define ") {
+            // define internal void @_ZN6header5greet17h4798ab2f1c9c3c6bE() unnamed_addr #0 {
+            if let Some(n) = line.find('@').and_then(|i| name(&line[i..])) {
+                ret.push((n.to_string(), true));
+            }
+        } else if line.starts_with('@') {
+            // @_ZN6header6GLOBAL17h0d2a3e0b6ab1c5f4E = internal global i32 0, align 4
+            // @_ZN3std2io5stdio6stdout17h...E = external global ...   <- not a definition
+            let rest = match line.find(" = ") {
+                Some(i) => &line[i + 3..],
+                None => continue,
+            };
+            let mut declaration = true;
+            for word in rest.split_whitespace() {
+                match word {
+                    "external" | "extern_weak" | "alias" | "ifunc" => break,
+                    "global" | "constant" => {
+                        declaration = false;
+                        break;
+                    },
+                    _ => (),
+                }
+            }
+            if let (false, Some(n)) = (declaration, name(&line)) {
+                ret.push((n.to_string(), false));
+            }
+        }
+    }
+    ret
+}
+
+/// What the dylib can get from std and the libs in its dependency list, by linkage name. With
+/// `interfaces_only`, only the dependencies that are interface libs count.
+fn provided_symbols(pair: Pair, package: &Lib, std: &Path, interfaces_only: bool) -> Result<HashSet<String>, String> {
+    let mut providers = vec![std.to_owned()];
+    for dep in package.dependencies {
+        if !interfaces_only || LIBS.iter().any(|l| l.name == *dep && l.has_exports) {
+            providers.push(Path::new(&pair.target()).join(pair.libname(dep)));
+        }
+    }
+    let mut provided = HashSet::new();
+    for path in &providers {
+        for sym in symbols::dynamic_symbols(path)? {
+            if sym.defined {
+                provided.insert(sym.name);
+            }
+        }
+    }
+    Ok(provided)
+}
+
+/// Turns the dylib's definitions of anything that std or its interface libs export into
+/// declarations, so that it uses their copy instead of linking in its own. Returns the bitcode
+/// files to link.
+fn strip_interface(
+    toolchain: &Toolchain,
+    pair: Pair,
+    package: &Lib,
+    std: &Path,
+    objects: Vec<String>,
+) -> Vec<String> {
+    let provided = unwrap(provided_symbols(pair, package, std, true));
+    let stripped_dir = format!("{}/deps/stripped", pair.target());
+    std::fs::create_dir_all(&stripped_dir)
+        .unwrap_or_else(|e| panic!("unable to create {:?}: {}", stripped_dir, e));
+    let mut ret = vec![];
+    for input in objects {
+        let mut dis = toolchain.get(pair, "llvm-dis", &[("$OBJECTS", input.clone())]);
+        dis.stdout(Stdio::piped());
+        let dis_cmd = format!("{:?}", dis);
+        let mut dis = dis.spawn().expect("failed to spawn llvm-dis");
+        let defs = bitcode_definitions(BufReader::new(dis.stdout.as_mut().expect("llvm-dis stdout")));
+        if !dis.wait().expect("wait on llvm-dis").success() {
+            println!("aborting due to failure of llvm-dis");
+            println!("  {}", dis_cmd);
+            exit();
+        }
+        let mut delete = String::new();
+        for (name, function) in defs {
+            if !provided.contains(&name) { continue; }
+            // Every dylib has its own.
+            if name == fingerprint::SYMBOL { continue; }
+            if !delete.is_empty() {
+                delete.push('\n');
+            }
+            write!(delete, "--{}={}", if function { "func" } else { "glob" }, name).unwrap();
+        }
+        if delete.is_empty() {
+            ret.push(input);
+            continue;
+        }
+        let file_name = Path::new(&input).file_name().expect("object has no file name");
+        let out = format!("{}/{}", stripped_dir, file_name.to_str().unwrap());
+        run(toolchain.get(pair, "llvm-extract", &[
+            ("$DELETE", delete),
+            ("$OUT", out.clone()),
+            ("$INPUT", input),
+        ]), "llvm-extract");
+        ret.push(out);
+    }
+    ret
+}
+
+/// Makes sure the dylib gets everything its interface libs export from them, eg that
+/// `header::greet` resolves to libheader's: a copy of its own would have its own statics, and
+/// wouldn't change along with header. Stripping its bitcode takes them out; this is what stops
+/// them getting through when the tools for that are missing.
+fn check_interface_copies(pair: Pair, package: &Lib, lib: &Path) -> Result<(), String> {
+    let mut exported = HashMap::new();
+    for dep in package.dependencies {
+        if !LIBS.iter().any(|l| l.name == *dep && l.has_exports) { continue; }
+        let path = Path::new(&pair.target()).join(pair.libname(dep));
+        for sym in symbols::dynamic_symbols(&path)? {
+            // Everybody has their own of these.
+            if !sym.defined || LINKER_SYMBOLS.contains(&&*sym.name) || sym.name == fingerprint::SYMBOL { continue; }
+            exported.insert(sym.name, *dep);
+        }
+    }
+    let mut copies = vec![];
+    for sym in symbols::defined_symbols(lib)? {
+        if let Some(dep) = exported.get(&sym.name) {
+            copies.push(format!("{}  (exported by {})", sym.demangled(), dep));
+        }
+    }
+    if copies.is_empty() {
+        return Ok(());
+    }
+    copies.sort();
+    let mut msg = format!("{} has its own copies of code that its interface libs export:", lib.display());
+    for c in &copies {
+        write!(msg, "\n    {}", c).ok();
+    }
+    Err(msg)
+}
+
+/// Looks for the dylib's own copies of code from the interface libs it depends on. Copies of
+/// anything the interface lib exports are `check_interface_copies`' problem, so what's left are
+/// instantiations of its generics (and `#[inline]` functions) that it doesn't export. Each plugin
+/// compiles its own, which is bloat, and they can drift apart from the interface lib's version.
+///
+/// The fix is to export the instantiation from the interface lib with `header::instantiate!`.
+fn check_generic_copies(package: &Lib, lib: &Path) -> Result<(), String> {
+    let mut copies = vec![];
+    let mut total = 0;
+    for sym in symbols::defined_symbols(lib)? {
+        let krate = match symbols::crate_of(&sym.name) {
+            Some(c) => c,
+            None => continue,
+        };
+        let interface = package
+            .dependencies
+            .iter()
+            .any(|dep| *dep == krate && LIBS.iter().any(|l| l.name == *dep && l.has_exports));
+        if interface {
+            total += sym.size;
+            copies.push((sym.demangled(), krate, sym.size));
+        }
+    }
+    if copies.is_empty() {
+        return Ok(());
+    }
+    copies.sort();
+    // Legacy-mangled names don't say which instantiation (or whether it's generic at all), so
+    // this can't be any more specific.
+    let mut msg = format!(
+        "{} has its own copies of {} functions ({} bytes) from interface crates that they don't \
+         export, so instantiations of their generics or their #[inline] functions:",
+        lib.display(),
+        copies.len(),
+        total,
+    );
+    for (path, krate, size) in &copies {
+        write!(msg, "\n    {}  ({}, {} bytes)", path, krate, size).ok();
+    }
+    msg.push_str("\n(instantiate! the generic ones in the interface crate so that everyone shares its copy)");
+    Err(msg)
+}
+
+/// Makes sure that everything the dylib expects to find at load time is provided by std, the
+/// libs in its dependency list, or the system libraries.
+fn check_undefined(pair: Pair, package: &Lib, lib: &Path, std: &Path) -> Result<(), String> {
+    let mut provided = provided_symbols(pair, package, std, false)?;
+    // The system libraries only match if we're running on the target. Only the ones the dylib
+    // asks for: not everything app has loaded, which includes its own header & std.
+    let mut system = vec![];
+    if pair.host == pair.target {
+        let deps: Vec<PathBuf> = package.dependencies.iter().map(|d| Path::new(&pair.target()).join(pair.libname(d))).collect();
+        let mut dylibs = vec![lib, std];
+        dylibs.extend(deps.iter().map(|d| &**d));
+        system = symbols::system_libs(&dylibs)?;
+    }
+    for path in &system {
+        for sym in symbols::dynamic_symbols(path)? {
+            if sym.defined {
+                provided.insert(sym.name);
+            }
+        }
+    }
+    let mut missing = vec![];
+    for sym in symbols::dynamic_symbols(lib)? {
+        if sym.defined || sym.weak || provided.contains(&sym.name) { continue; }
+        let from = match symbols::crate_of(&sym.name) {
+            Some(c) if c == "std" || c == "core" || c == "alloc" => "std".to_string(),
+            Some(c) => c,
+            // Can't say anything about C symbols without the system libraries at hand.
+            None if system.is_empty() => continue,
+            None => "libc".to_string(),
+        };
+        missing.push((from, sym.demangled()));
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
+    missing.sort();
+    let mut msg = format!("{} has undefined symbols that nothing provides:", lib.display());
+    for (from, name) in &missing {
+        write!(msg, "\n    {}  (expected from {})", name, from).ok();
+    }
+    Err(msg)
+}
+
+/// Symbols the linker defines in every dylib.
+static LINKER_SYMBOLS: &[&str] = &[
+    "_init",
+    "_fini",
+    "_edata",
+    "_end",
+    "__bss_start",
+    "_DYNAMIC",
+    "_GLOBAL_OFFSET_TABLE_",
+];
+
+/// Looks for symbols exported by more than one of the dylibs. std, the interface libs and the
+/// host are loaded with `RTLD_GLOBAL` (or were there to begin with), and their symbols beat
+/// anybody else's: a collision with one of them means a plugin silently gets somebody else's
+/// code. `plugins` are loaded with `RTLD_LOCAL`, so collisions between just them don't matter
+/// to the loader. Gives those separately, after the others.
+fn check_collisions(libs: &[PathBuf], plugins: &[PathBuf]) -> (Result<(), String>, Result<(), String>) {
+    let mut owners: HashMap<String, Vec<&Path>> = HashMap::new();
+    for lib in libs {
+        let syms = match symbols::dynamic_symbols(lib) {
+            Ok(syms) => syms,
+            Err(e) => return (Err(e), Ok(())),
+        };
+        for sym in syms {
+            if !sym.defined || LINKER_SYMBOLS.contains(&&*sym.name) { continue; }
+            // Everybody has one of these; they're looked up in a specific dylib.
+            if sym.name == fingerprint::SYMBOL { continue; }
+            owners.entry(sym.name).or_default().push(lib);
+        }
+    }
+    let mut shared = vec![];
+    let mut between_plugins = vec![];
+    for (name, owners) in owners {
+        if owners.len() < 2 { continue; }
+        // Copies of an interface crate's symbol are all the same code, so it doesn't matter
+        // which one gets used. (Whether they should be there at all is check_bloat's problem.)
+        if symbols::crate_of(&name).map(|c| is_interface(&c)) == Some(true) { continue; }
+        let only_plugins = owners.iter().all(|o| plugins.iter().any(|p| p == o));
+        let owners = owners
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let c = format!("{}  (in {})", symbols::demangle(&name), owners);
+        if only_plugins {
+            between_plugins.push(c);
+        } else {
+            shared.push(c);
+        }
+    }
+    let report = |mut collisions: Vec<String>, what: &str| {
+        if collisions.is_empty() {
+            return Ok(());
+        }
+        collisions.sort();
+        let mut msg = format!("multiple {} export the same symbol:", what);
+        for c in &collisions {
+            write!(msg, "\n    {}", c).ok();
+        }
+        Err(msg)
+    };
+    (report(shared, "dylibs"), report(between_plugins, "plugins"))
+}
+
+/// Puts a copy of libstd beside the dylibs, where their `$ORIGIN` RUNPATH will find it.
+fn install_std(pair: Pair, std: &Path) {
+    let name = std.file_name().expect("std has no file name");
+    let dest = Path::new(&pair.target()).join(name);
+    if modified(&dest) >= modified(std) {
+        return;
+    }
+    std::fs::copy(std, &dest)
+        .unwrap_or_else(|e| panic!("unable to copy {:?} to {:?}: {}", std, dest, e));
+}
+
+/// Lists what the dylib carries from std and the interface libs it depends on. That's the
+/// "base crate gets all its symbols embedded" problem. Mostly it's instantiations of their
+/// generics, which a dylib has no choice but to bring itself, so the list is up to `bloat`.
+/// (Interface libs are allowed their own; that's where everybody else's are meant to come from.)
+///
+/// A copy of something that std or an interface lib exports is never needed, though, so that's
+/// an error; unless the dylib wasn't `stripped`, in which case there was no helping it.
+fn check_bloat(pair: Pair, package: &Lib, lib: &Path, std: &Path, bloat: Policy, stripped: bool) -> Result<(), String> {
+    let provided = provided_symbols(pair, package, std, true)?;
+    let mut carried = vec![];
+    let mut total = 0;
+    let mut copies = vec![];
+    let mut copied = 0;
+    for sym in symbols::defined_symbols(lib)? {
+        let krate = match symbols::crate_of(&sym.name) {
+            Some(c) if c != package.name && is_interface(&c) => c,
+            _ => continue,
+        };
+        let path = sym.demangled();
+        // By linkage name: the demangled path is the same for every instantiation of a generic.
+        if provided.contains(&sym.name) {
+            copied += sym.size;
+            copies.push((path.clone(), krate.clone(), sym.size));
+        }
+        total += sym.size;
+        carried.push((path, krate, sym.size));
+    }
+    if !package.has_exports && !carried.is_empty() {
+        carried.sort();
+        let mut msg = format!(
+            "{} carries {} symbols ({} bytes) from std or interface crates:",
+            lib.display(),
+            carried.len(),
+            total,
+        );
+        for (path, krate, size) in &carried {
+            write!(msg, "\n    {}  ({}, {} bytes)", path, krate, size).ok();
+        }
+        if bloat == Policy::Deny {
+            msg.push_str("\n(--bloat=warn to build it anyway)");
+        }
+        bloat.enforce(Err(msg));
+    }
+    if copies.is_empty() {
+        return Ok(());
+    }
+    copies.sort();
+    let mut msg = format!(
+        "{} has its own copies of {} symbols ({} bytes) that std or interface crates export:",
+        lib.display(),
+        copies.len(),
+        copied,
+    );
+    for (path, krate, size) in &copies {
+        write!(msg, "\n    {}  ({}, {} bytes)", path, krate, size).ok();
+    }
+    if !stripped {
+        msg.push_str("\n(they weren't stripped out of its bitcode; the tools for it are missing)");
+        bloat.enforce(Err(msg));
+        return Ok(());
+    }
+    Err(msg)
 }
 
 fn main() {
@@ -617,9 +1321,17 @@ fn main() {
         host: HOST,
         target: HOST,
     };
-    let (std, header, plugin) = if let Some(ref toolchain) = Toolchain::load() {
-        let std = find_rust_std(toolchain, native, "header").expect("failed to find rust std");
-
+    if std::env::args().nth(1).as_deref() == Some("inspect") {
+        inspect::main(native);
+        return;
+    }
+    let collisions = Policy::from_args("collisions", Policy::Deny);
+    let plugin_collisions = Policy::from_args("plugin-collisions", Policy::Allow);
+    let enforce = |(shared, between_plugins)| {
+        collisions.enforce(shared);
+        plugin_collisions.enforce(between_plugins);
+    };
+    let header = if let Some(ref toolchain) = Toolchain::load() {
         if toolchain.compile {
             // FIXME: Copy in libstd.so, std.dll, std.dll.lib, msvrt.{lib,dll}
         }
@@ -634,85 +1346,253 @@ fn main() {
                 },
             });
         }
-        let mut hp = None;
+        let mut built_header = None;
         for &pair in pairs.iter().rev() {
             if pair.foreign() {
                 println!("   Toolchain target {}", pair.target);
             }
-            hp = Some((
-                compile_dylib(toolchain, pair, Lib {
-                    name: "header",
-                    has_exports: true,
-                    dependencies: &[],
-                }),
-                compile_dylib(toolchain, pair, Lib {
-                    name: "plugin",
-                    has_exports: false,
-                    dependencies: &["header"],
-                }),
-            ));
+            // All of cargo's work first: each time it runs, it puts its own build of header
+            // back where the builder's goes.
+            let std = find_rust_std(toolchain, pair, LIBS[0].name).expect("failed to find rust std");
+            let metadata = cargo_metadata(toolchain, pair);
+            let emitted = LIBS
+                .iter()
+                .map(|lib| emit_bitcode(toolchain, pair, lib))
+                .collect::<Vec<String>>();
+            let built = LIBS
+                .iter()
+                .zip(emitted)
+                .map(|(lib, emitted)| compile_dylib(toolchain, pair, lib, &std, &metadata, emitted))
+                .collect::<Vec<PathBuf>>();
+            let plugins = LIBS
+                .iter()
+                .zip(&built)
+                .filter(|(lib, _)| !lib.has_exports)
+                .map(|(_, path)| path.clone())
+                .collect::<Vec<PathBuf>>();
+            enforce(check_collisions(&built, &plugins));
+            built_header = Some(built[0].clone());
         }
         if toolchain.compile { return; }
-        let (h, p) = hp.unwrap();
-        (std, h, p)
+        built_header.unwrap()
     } else {
-        // libstd has a hash appended. I'd rather it didn't, but the plugins refer to it by
-        // name with the hash. This code to find it could be a problem if there are multiple
-        // libstds -- it's very imaginable that an update process fails to delete the old one.
-        // What we could do is we could open up each of the libraries in turn, scanning for
-        // something matching /std-[a-zA-Z0-9]\{16}\.dll/. It is possible that it is included as a
-        // string, but I think we could stand to ignore that possibility.
-        (
-            unwrap(seek_lib(native, "std*")),
-            unwrap(seek_lib(native, "header")),
-            unwrap(seek_lib(native, "plugin")),
-        )
+        unwrap(seek_lib(native, "header"))
     };
-    my_guy();
-    #[cfg(target_os = "linux")]
-    unsafe {
-        pub use libloading::os::unix::*;
-        // FIXME: RTLD_GLOBAL: What if a library overrides the symbol of another?
-        // There's RTLD_DEEPBIND in libc, but it's not exposed here.
-        // The documentation isn't clear on what that means. Does it mean it can override other
-        // library's symbols? The name implies that. Does it mean that its own symbols take
-        // priority, and its symbols don't override others? The name implies this as well.
-        let _std = Library::open(Some(&std), RTLD_GLOBAL | RTLD_NOW).expect("load std");
-        my_guy();
-        let _header = Library::open(Some(header), RTLD_GLOBAL | RTLD_NOW).expect("load header");
-        my_guy();
-        let plugin = libloading::Library::new(plugin).expect("load plugin");
-        use_plugin(&plugin);
-    }
-    #[cfg(target_os = "windows")]
-    unsafe {
-        // NOTE: If running under wine, you may need to put vcruntime140d.dll by the .exe,
-        // if vcruntime isn't linked statically.
-        let _std = libloading::Library::new(&std).expect("load std.dll");
-        my_guy();
-        let _header = libloading::Library::new(header).expect("load header.dll");
-        my_guy();
-        let plugin = libloading::Library::new(plugin).expect("load plugin.dll");
-        my_guy();
-        use_plugin(&plugin);
-    }
-    my_guy();
-}
-
-fn use_plugin(plugin: &libloading::Library) {
-    println!("plugin: {:?}", plugin);
+    // The plugins live beside header; what they need can be anywhere on the search path. What
+    // gets loaded, and in what order, comes from their import tables.
+    let dir = header.parent().expect("header has no directory").to_owned();
+    let mut plugins = host::PluginHost::new();
+    if let Some(mode) = std::env::args().find_map(|a| a.strip_prefix("--plugin-mode=").map(str::to_string)) {
+        let mode = host::PluginMode::from_name(&mode).unwrap_or_else(|| {
+            println!("--plugin-mode=: expected local or deepbind; not {:?}", mode);
+            exit()
+        });
+        plugins.set_mode(mode);
+    }
+    for d in search_path("header") {
+        if d.is_dir() {
+            plugins.add_search_path(&d);
+        }
+    }
+    // FIXME: On Windows, that's app's own copy of header, not the one the plugins get.
+    header::set_host_functions(HOST_FUNCTIONS);
+    // libstd first, and exactly the one header was built against; a plugin wanting another
+    // one won't find it.
+    let std = unwrap(find_std(&header));
+    unwrap(plugins.load_interface(&std));
+    let mut plan = unwrap(plugins.plan(&[&dir]));
+    // Everything depends on the interface libs, so there's no going on without them; a plugin
+    // built differently just gets left out.
+    for path in &plan.interfaces {
+        // libstd hasn't got one; it's checked through the others'.
+        if unwrap(host::Manifest::read(path)).is_some() {
+            unwrap(check_fingerprint(path));
+        }
+    }
+    for (path, manifest) in std::mem::take(&mut plan.load) {
+        match check_fingerprint(&path) {
+            Ok(()) => plan.load.push((path, manifest)),
+            Err(e) => plan.skipped.push((path, e)),
+        }
+    }
+    for (path, why) in &plan.skipped {
+        println!("warning: skipping {}: {}", path.display(), why);
+    }
+    {
+        let loaded: Vec<PathBuf> = plan.load.iter().map(|(path, _)| path.clone()).collect();
+        let mut all = vec![std];
+        all.extend(plan.interfaces.iter().cloned());
+        all.extend(loaded.iter().cloned());
+        all.extend(std::env::current_exe().ok());
+        enforce(check_collisions(&all, &loaded));
+    }
+    for (path, why) in unwrap(plugins.load(&plan)) {
+        println!("warning: unable to load {}: {}", path.display(), why);
+    }
+    use_plugins(&plugins);
+    if std::env::args().any(|a| a == "--watch") {
+        watch(&mut plugins);
+    }
+    if let Some(service) = std::env::args().find_map(|a| a.strip_prefix("--namespaces=").map(str::to_string)) {
+        let paths: Vec<PathBuf> = plan.load.iter().map(|(path, _)| path.clone()).collect();
+        use_namespaces(&paths, &service);
+    }
+}
+
+/// `--watch`: reloads plugins as they're rebuilt (by `app --compile` in another terminal, say),
+/// and says hello with everything again after each time.
+fn watch(plugins: &mut host::PluginHost) -> ! {
+    println!("watching for rebuilt plugins; ^C to stop");
+    // So that a build that can't be loaded is only complained about once.
+    let mut refused: HashMap<PathBuf, Option<SystemTime>> = HashMap::new();
+    loop {
+        std::thread::sleep(Duration::from_millis(250));
+        let mut reloaded = false;
+        for path in plugins.changed() {
+            let modified = path.metadata().and_then(|m| m.modified()).ok();
+            if refused.get(&path) == Some(&modified) { continue; }
+            println!("reloading {}", path.display());
+            let result = check_fingerprint(&path).and_then(|()| plugins.reload(&path));
+            match result {
+                Ok(true) => (),
+                Ok(false) => println!("note: {} didn't take its old state", path.display()),
+                Err(e) => {
+                    println!("warning: {}", e);
+                    refused.insert(path, modified);
+                    continue;
+                },
+            }
+            refused.remove(&path);
+            reloaded = true;
+        }
+        if !reloaded { continue; }
+        header::set(1);
+        for (_, name) in plugins.services::<dyn SayHelloService>() {
+            println!("{}:", name);
+            // Dropped straight away, so that nothing's in the way of the next reload.
+            let said = plugins
+                .create::<dyn SayHelloService>(name)
+                .and_then(|service| Ok(service.call(|s| s.say_hello())?));
+            if let Err(e) = said {
+                println!("warning: {}", e);
+            }
+        }
+    }
+}
+
+/// `--namespaces=<service>`: loads each plugin twice more, each copy in a namespace of its own
+/// with its own header, beside the one that's already loaded, and says hello with the service
+/// from each. Not "hello": that one expects header's state to have been set up by us, and
+/// a namespace's header is a fresh one.
+#[cfg(target_os = "linux")]
+fn use_namespaces(paths: &[PathBuf], service: &str) {
+    use host::namespace::Namespace;
+    for path in paths {
+        let copies = [
+            unwrap(Namespace::load(path, HOST_FUNCTIONS)),
+            unwrap(Namespace::load(path, HOST_FUNCTIONS)),
+        ];
+        for (i, ns) in copies.iter().enumerate() {
+            println!("{} (namespace {}, which has {}):", service, i, ns.names::<dyn SayHelloService>().join(", "));
+            unwrap(ns.call::<dyn SayHelloService>(service, "say_hello"));
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn use_namespaces(_: &[PathBuf], _: &str) {
+    println!("--namespaces needs dlmopen, which only Linux has");
+    exit()
+}
+
+/// The libstd a dylib needs. Its import table names it, as `libstd-<hash>.so` or
+/// `std-<hash>.dll`; its fingerprint had better agree. It has to be that exact file: another
+/// libstd left behind by an update won't do.
+fn find_std(lib: &Path) -> Result<PathBuf, String> {
+    let needed = host::needed(lib)?;
+    let mut stds = needed.iter().filter(|n| fingerprint::std_hash(Path::new(n)).is_some());
+    let name = match (stds.next(), stds.next()) {
+        (Some(name), None) => name,
+        (None, _) => return Err(format!("{} doesn't link against libstd", lib.display())),
+        (Some(a), Some(b)) => return Err(format!("{} links against two libstds: {} and {}", lib.display(), a, b)),
+    };
+    let hash = fingerprint::std_hash(Path::new(name)).unwrap_or_default();
+    if let Some(fp) = symbols::read_cstr(lib, fingerprint::SYMBOL)? {
+        let fp = Fingerprint::decode(&fp)?;
+        if fp.std != hash {
+            return Err(format!(
+                "{} links against {}, but its fingerprint says it was built with std {}",
+                lib.display(),
+                name,
+                fp.std,
+            ));
+        }
+    }
+    let mut dirs: Vec<PathBuf> = lib.parent().map(Path::to_path_buf).into_iter().collect();
+    for d in search_path("std") {
+        if !dirs.contains(&d) {
+            dirs.push(d);
+        }
+    }
+    if let Some(path) = dirs.iter().map(|d| d.join(name)).find(|p| p.is_file()) {
+        return Ok(path);
+    }
+    let mut msg = format!("{} needs {}, which isn't in any of:", lib.display(), name);
+    for d in &dirs {
+        write!(msg, "\n  {}", d.display()).ok();
+    }
+    Err(msg)
+}
+
+/// Makes sure the dylib was built with the same rustc, std, header & profile as the host.
+fn check_fingerprint(lib: &Path) -> Result<(), String> {
+    let host = Fingerprint::decode(include_str!(concat!(env!("OUT_DIR"), "/fingerprint.txt")))?;
+    let theirs = match symbols::read_cstr(lib, fingerprint::SYMBOL)? {
+        Some(fp) => Fingerprint::decode(&fp)?,
+        None => return Err(format!("{} has no fingerprint; it wasn't built by this app", lib.display())),
+    };
+    let mismatches = host.mismatches(&theirs);
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+    let mut msg = format!("{} was built differently from the host:", lib.display());
+    for (field, ours, theirs) in mismatches {
+        write!(msg, "\n    {:<8} host: {:?}\n    {:<8} dylib: {:?}", field, ours, "", theirs).ok();
+    }
+    Err(msg)
+}
+
+/// What header's `host_functions!` call.
+const HOST_FUNCTIONS: header::HostFunctions = header::HostFunctions { app_name };
+
+fn app_name() -> String {
+    "app".to_string()
+}
+
+fn use_plugins(plugins: &host::PluginHost) {
+    for p in plugins.plugins() {
+        match p.manifest() {
+            Some(m) => println!("plugin: {} {} ({}): {}", m.name, m.version, p.path().display(), m.description),
+            None => println!("plugin: {}", p.path().display()),
+        }
+    }
     assert_eq!(header::get(), 0);
     header::set(1);
     assert_eq!(header::get(), 1);
-    type F = extern "Rust" fn() -> Box<dyn SayHelloService>;
-    let new_service: libloading::Symbol<F> = unsafe { plugin.get(b"new_service").expect("load symbol") };
-    let service = new_service();
-    service.say_hello();
-    /*assert_eq!(header::get(), 2);*/
+    for (_, name) in plugins.services::<dyn SayHelloService>() {
+        println!("{}:", name);
+        // A plugin that panics gets disabled, and we carry on without it.
+        let said = plugins
+            .create::<dyn SayHelloService>(name)
+            .and_then(|service| Ok(service.call(|s| s.say_hello())?));
+        if let Err(e) = said {
+            println!("warning: {}", e);
+        }
+    }
+    // (Unless "hello" didn't get to set it: the plugin didn't load, or it panicked and was
+    // disabled. Or on Windows, where app still has its own copy of header.)
+    if cfg!(unix) && plugins.services::<dyn SayHelloService>().iter().any(|(_, name)| *name == "hello") {
+        assert_eq!(header::get(), 2);
+    }
     println!("Hooray!");
 }
-
-#[no_mangle]
-fn my_guy() {
-    // plugin.rs' panics
-}
@@ -0,0 +1,43 @@
+#[allow(dead_code)]
+mod fingerprint;
+
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    // app links libheader.so & libstd.so, which the builder puts beside it. (Until it's been run
+    // once, there's no libstd.so there; `cargo run` finds the toolchain's.)
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("linux") {
+        println!("cargo:rustc-link-arg-bins=-Wl,-rpath,$ORIGIN");
+    }
+
+    // The host's fingerprint, for comparing against the dylibs'.
+    let rustc = std::env::var("RUSTC").unwrap();
+    let libdir = Command::new(&rustc)
+        .args(["--print", "target-libdir"])
+        .output()
+        .expect("rustc --print target-libdir");
+    let libdir = String::from_utf8(libdir.stdout).unwrap();
+    let std = std::fs::read_dir(libdir.trim())
+        .expect("read target-libdir")
+        .flatten()
+        .find_map(|e| {
+            let path = e.path();
+            let dylib = matches!(path.extension().and_then(|e| e.to_str()), Some("so" | "dll"));
+            if dylib { fingerprint::std_hash(&path) } else { None }
+        })
+        .unwrap_or_default();
+    let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let workspace = Path::new(&dir).join("..");
+    let fp = fingerprint::Fingerprint {
+        rustc: fingerprint::rustc_version(Command::new(&rustc)),
+        std,
+        header: fingerprint::source_hash(&workspace, fingerprint::HEADER_SOURCES),
+        profile: std::env::var("PROFILE").unwrap(),
+    };
+    let out = Path::new(&std::env::var("OUT_DIR").unwrap()).join("fingerprint.txt");
+    std::fs::write(&out, fp.encode()).expect("write fingerprint.txt");
+    for f in fingerprint::HEADER_SOURCES {
+        println!("cargo:rerun-if-changed=../{}", f);
+    }
+}
@@ -0,0 +1,102 @@
+//! What a dylib has to have in common with the host for it to be safe to load.
+//!
+//! The builder puts one of these in every dylib as the `PLUGINS3_FINGERPRINT` symbol, and
+//! `build.rs` works out the host's own. This file is shared between the two.
+
+use std::path::Path;
+use std::process::Command;
+
+/// The symbol holding the fingerprint, as a nul-terminated string.
+pub const SYMBOL: &str = "PLUGINS3_FINGERPRINT";
+
+/// header's sources, relative to the workspace.
+pub const HEADER_SOURCES: &[&str] = &["header/header.rs", "header/Cargo.toml"];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Fingerprint {
+    /// `rustc -V`
+    pub rustc: String,
+    /// The hash in libstd's file name.
+    pub std: String,
+    /// A hash of header's sources.
+    pub header: String,
+    /// debug or release
+    pub profile: String,
+}
+
+impl Fingerprint {
+    fn fields(&self) -> [(&'static str, &str); 4] {
+        [
+            ("rustc", &self.rustc),
+            ("std", &self.std),
+            ("header", &self.header),
+            ("profile", &self.profile),
+        ]
+    }
+
+    pub fn encode(&self) -> String {
+        let mut ret = String::new();
+        for (k, v) in &self.fields() {
+            ret.push_str(&format!("{}={}\n", k, v));
+        }
+        ret
+    }
+
+    pub fn decode(text: &str) -> Result<Fingerprint, String> {
+        let mut ret = Fingerprint {
+            rustc: String::new(),
+            std: String::new(),
+            header: String::new(),
+            profile: String::new(),
+        };
+        for line in text.lines() {
+            let (k, v) = line.split_once('=').ok_or_else(|| format!("bad fingerprint line {:?}", line))?;
+            let field = match k {
+                "rustc" => &mut ret.rustc,
+                "std" => &mut ret.std,
+                "header" => &mut ret.header,
+                "profile" => &mut ret.profile,
+                _ => continue,
+            };
+            *field = v.to_string();
+        }
+        Ok(ret)
+    }
+
+    /// `(field, ours, theirs)` for each field that differs.
+    pub fn mismatches<'a>(&'a self, other: &'a Fingerprint) -> Vec<(&'static str, &'a str, &'a str)> {
+        let mut ret = vec![];
+        for ((k, a), (_, b)) in self.fields().iter().zip(other.fields().iter()) {
+            if a != b {
+                ret.push((*k, *a, *b));
+            }
+        }
+        ret
+    }
+}
+
+pub fn rustc_version(mut rustc: Command) -> String {
+    let out = rustc.arg("-V").output().unwrap_or_else(|e| panic!("unable to run {:?}: {}", rustc, e));
+    String::from_utf8_lossy(&out.stdout).trim().to_string()
+}
+
+/// `libstd-d1237ef7159db0a2.so` or `std-d1237ef7159db0a2.dll` gives `d1237ef7159db0a2`.
+pub fn std_hash(std: &Path) -> Option<String> {
+    let name = std.file_stem()?.to_str()?;
+    let name = name.strip_prefix("lib").unwrap_or(name);
+    Some(name.strip_prefix("std-")?.split('.').next()?.to_string())
+}
+
+/// FNV-1a over the files' contents. Not cryptographic; this is to catch mistakes.
+pub fn source_hash(workspace: &Path, files: &[&str]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for f in files {
+        let path = workspace.join(f);
+        let data = std::fs::read(&path).unwrap_or_else(|e| panic!("unable to read {:?}: {}", path, e));
+        for b in data {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
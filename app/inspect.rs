@@ -0,0 +1,216 @@
+//! `app inspect --size`: where the bytes in each dylib come from.
+//!
+//! Before linking over a dylib, the build saves the old one's numbers beside it
+//! (`libplugin.so.size`), and this compares against them. `--budget=plugin:4096` complains if
+//! `libplugin.so` grew by more than that since the previous build.
+
+use crate::{exit, is_interface, seek_lib, symbols, unwrap, Pair, LIBS};
+use object::{SectionKind, SymbolKind};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Category {
+    /// The dylib's own functions, including generics instantiated with its own types, and
+    /// `#[no_mangle]` functions.
+    Own,
+    /// Copies of functions from interface crates, eg `header::greet`.
+    Interface,
+    /// std, core & alloc monomorphizations, and inlined std functions.
+    Std,
+    /// Functions from any other crate.
+    OtherCode,
+    /// Statics, vtables, string constants.
+    Data,
+    /// Headers, symbol tables, relocations, debug info, padding.
+    Other,
+}
+use self::Category::*;
+
+const CATEGORIES: [Category; 6] = [Own, Interface, Std, OtherCode, Data, Other];
+
+impl Category {
+    /// The name in the saved `.size` file.
+    fn key(self) -> &'static str {
+        match self {
+            Own => "own",
+            Interface => "interface",
+            Std => "std",
+            OtherCode => "other-code",
+            Data => "data",
+            Other => "other",
+        }
+    }
+    fn describe(self, own: &str) -> String {
+        match self {
+            Own => format!("{} code", own),
+            Interface => "interface copies".into(),
+            Std => "std monomorphizations".into(),
+            OtherCode => "other code".into(),
+            Data => "data".into(),
+            Other => "other".into(),
+        }
+    }
+}
+
+struct Breakdown {
+    total: u64,
+    bytes: HashMap<&'static str, u64>,
+}
+
+/// Whether a demangled path mentions anything from `krate`, as in
+/// `core::ptr::drop_in_place<plugin::PluginSayHello>`.
+fn mentions(path: &str, krate: &str) -> bool {
+    let needle = format!("{}::", krate);
+    path.match_indices(&needle).any(|(i, _)| {
+        path[..i].chars().next_back().map(|c| !(c.is_alphanumeric() || c == '_')) != Some(false)
+    })
+}
+
+fn breakdown(path: &Path, own: &str) -> Result<Breakdown, String> {
+    let total = path
+        .metadata()
+        .map_err(|e| format!("unable to stat {:?}: {}", path, e))?
+        .len();
+    let mut bytes: HashMap<&'static str, u64> = HashMap::new();
+    for sym in symbols::defined_symbols(path)? {
+        if sym.kind != SymbolKind::Text { continue; }
+        let category = match symbols::crate_of(&sym.name) {
+            None => Own,
+            _ if mentions(&sym.demangled(), own) => Own,
+            Some(c) if matches!(&*c, "std" | "core" | "alloc") => Std,
+            Some(c) if is_interface(&c) => Interface,
+            Some(_) => OtherCode,
+        };
+        *bytes.entry(category.key()).or_default() += sym.size;
+    }
+    for section in symbols::sections(path)? {
+        let data = matches!(
+            section.kind,
+            SectionKind::ReadOnlyData
+                | SectionKind::ReadOnlyDataWithRel
+                | SectionKind::ReadOnlyString
+                | SectionKind::Data
+                | SectionKind::Tls
+        );
+        if data {
+            *bytes.entry(Data.key()).or_default() += section.file_size;
+        }
+    }
+    let counted: u64 = bytes.values().sum();
+    bytes.insert(Other.key(), total.saturating_sub(counted));
+    Ok(Breakdown { total, bytes })
+}
+
+fn record_path(dylib: &Path) -> PathBuf {
+    let mut name = dylib.as_os_str().to_owned();
+    name.push(".size");
+    name.into()
+}
+
+fn load_record(dylib: &Path) -> Option<Breakdown> {
+    let text = std::fs::read_to_string(record_path(dylib)).ok()?;
+    let mut ret = Breakdown { total: 0, bytes: HashMap::new() };
+    for line in text.lines() {
+        let mut line = line.split_whitespace();
+        let (key, n) = match (line.next(), line.next().and_then(|n| n.parse::<u64>().ok())) {
+            (Some(key), Some(n)) => (key, n),
+            _ => continue,
+        };
+        if key == "total" {
+            ret.total = n;
+        } else if let Some(c) = CATEGORIES.iter().find(|c| c.key() == key) {
+            ret.bytes.insert(c.key(), n);
+        }
+    }
+    Some(ret)
+}
+
+fn save_record(dylib: &Path, b: &Breakdown) {
+    let mut text = format!("total {}\n", b.total);
+    for c in &CATEGORIES {
+        text.push_str(&format!("{} {}\n", c.key(), b.bytes.get(c.key()).unwrap_or(&0)));
+    }
+    let path = record_path(dylib);
+    std::fs::write(&path, text).unwrap_or_else(|e| panic!("unable to write {:?}: {}", path, e));
+}
+
+/// Records the size of the dylib that's about to be replaced, so `app inspect --size` can compare
+/// the new build against it.
+pub fn record_previous(dylib: &Path) {
+    let own = own_name(dylib);
+    save_record(dylib, &unwrap(breakdown(dylib, own)));
+}
+
+fn own_name(dylib: &Path) -> &str {
+    let name = dylib
+        .file_stem()
+        .and_then(|n| n.to_str())
+        .unwrap_or_else(|| panic!("bad dylib name {:?}", dylib));
+    name.strip_prefix("lib").unwrap_or(name)
+}
+
+fn delta(now: u64, then: Option<u64>) -> String {
+    match then {
+        Some(then) if now != then => format!("  ({:+})", now as i64 - then as i64),
+        _ => String::new(),
+    }
+}
+
+/// Prints the breakdown of one dylib against the previous build. Returns false if it went over
+/// budget.
+fn report(dylib: &Path, budgets: &HashMap<String, u64>) -> bool {
+    let own = own_name(dylib);
+    let now = unwrap(breakdown(dylib, own));
+    let then = load_record(dylib);
+    println!("{}: {} bytes{}", dylib.display(), now.total, delta(now.total, then.as_ref().map(|t| t.total)));
+    for c in &CATEGORIES {
+        let n = *now.bytes.get(c.key()).unwrap_or(&0);
+        let was = then.as_ref().map(|t| *t.bytes.get(c.key()).unwrap_or(&0));
+        println!("    {:<24}{:>10}{}", c.describe(own), n, delta(n, was));
+    }
+    if let (Some(budget), Some(then)) = (budgets.get(own), then) {
+        let growth = now.total.saturating_sub(then.total);
+        if growth > *budget {
+            println!("    OVER BUDGET: grew by {} bytes; {} is allowed", growth, budget);
+            return false;
+        }
+    }
+    true
+}
+
+pub fn main(native: Pair) {
+    let args: Vec<String> = std::env::args().skip(2).collect();
+    if !args.iter().any(|a| a == "--size") {
+        println!("usage: app inspect --size [--budget=<plugin>:<bytes>]... [dylib]...");
+        exit()
+    }
+    let mut budgets = HashMap::new();
+    let mut dylibs = vec![];
+    for arg in &args {
+        if let Some(budget) = arg.strip_prefix("--budget=") {
+            let (name, bytes) = budget
+                .split_once(':')
+                .and_then(|(name, bytes)| Some((name, bytes.parse::<u64>().ok()?)))
+                .unwrap_or_else(|| {
+                    println!("expected --budget=<plugin>:<bytes>, not {:?}", arg);
+                    exit()
+                });
+            budgets.insert(name.to_string(), bytes);
+        } else if !arg.starts_with("--") {
+            dylibs.push(PathBuf::from(arg));
+        }
+    }
+    if dylibs.is_empty() {
+        for lib in LIBS {
+            dylibs.push(unwrap(seek_lib(native, lib.name)));
+        }
+    }
+    let mut ok = true;
+    for dylib in &dylibs {
+        ok &= report(dylib, &budgets);
+    }
+    if !ok {
+        exit()
+    }
+}
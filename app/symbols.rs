@@ -0,0 +1,245 @@
+//! Reading the symbol tables of built dylibs.
+
+use object::{Object, ObjectSection, ObjectSymbol, SectionKind, SymbolKind};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    /// The linkage name, eg `_ZN6header5greet17h7991ffbe918cc6e2E`.
+    pub name: String,
+    pub defined: bool,
+    pub weak: bool,
+    pub kind: SymbolKind,
+    /// Bytes, if the format records it.
+    pub size: u64,
+}
+
+impl Symbol {
+    pub fn demangled(&self) -> String {
+        demangle(&self.name)
+    }
+}
+
+fn parse<T>(path: &Path, f: impl FnOnce(&object::File) -> Result<T, String>) -> Result<T, String> {
+    let data = std::fs::read(path).map_err(|e| format!("unable to read {:?}: {}", path, e))?;
+    let file = object::File::parse(&*data).map_err(|e| format!("unable to parse {:?}: {}", path, e))?;
+    f(&file)
+}
+
+/// A dylib's dynamic symbols: what it exports, and what it expects somebody else to provide.
+pub fn dynamic_symbols(path: &Path) -> Result<Vec<Symbol>, String> {
+    parse(path, |file| read_dynamic(path, file))
+}
+
+/// Everything defined in a dylib, local symbols included. This needs the full symbol table;
+/// if the dylib has been stripped (or is a DLL), it's only what it exports.
+pub fn defined_symbols(path: &Path) -> Result<Vec<Symbol>, String> {
+    parse(path, |file| {
+        let mut ret = vec![];
+        for sym in file.symbols() {
+            if sym.is_undefined() { continue; }
+            if !matches!(sym.kind(), SymbolKind::Text | SymbolKind::Data | SymbolKind::Tls) { continue; }
+            let name = match sym.name() {
+                Ok(name) if !name.is_empty() => name,
+                _ => continue,
+            };
+            ret.push(Symbol {
+                name: name.to_string(),
+                defined: true,
+                weak: sym.is_weak(),
+                kind: sym.kind(),
+                size: sym.size(),
+            });
+        }
+        if ret.is_empty() {
+            ret = read_dynamic(path, file)?;
+            ret.retain(|s| s.defined);
+        }
+        Ok(ret)
+    })
+}
+
+fn read_dynamic(path: &Path, file: &object::File) -> Result<Vec<Symbol>, String> {
+    let mut ret = vec![];
+    if let object::File::Pe64(_) | object::File::Pe32(_) = *file {
+        // PE has no dynamic symbol table; there's an export directory and an import directory.
+        let exports = file.exports().map_err(|e| format!("bad export table in {:?}: {}", path, e))?;
+        for e in exports {
+            ret.push(Symbol {
+                name: String::from_utf8_lossy(e.name()).into_owned(),
+                defined: true,
+                weak: false,
+                kind: SymbolKind::Unknown,
+                size: 0,
+            });
+        }
+        let imports = file.imports().map_err(|e| format!("bad import table in {:?}: {}", path, e))?;
+        for i in imports {
+            ret.push(Symbol {
+                name: String::from_utf8_lossy(i.name()).into_owned(),
+                defined: false,
+                weak: false,
+                kind: SymbolKind::Unknown,
+                size: 0,
+            });
+        }
+        return Ok(ret);
+    }
+    for sym in file.dynamic_symbols() {
+        let name = match sym.name() {
+            Ok(name) if !name.is_empty() => name,
+            _ => continue,
+        };
+        ret.push(Symbol {
+            name: name.to_string(),
+            defined: !sym.is_undefined(),
+            weak: sym.is_weak(),
+            kind: sym.kind(),
+            size: sym.size(),
+        });
+    }
+    Ok(ret)
+}
+
+/// Reads a nul-terminated string that a dylib exports, without loading it.
+pub fn read_cstr(path: &Path, name: &str) -> Result<Option<String>, String> {
+    parse(path, |file| {
+        let address = match address_of(path, file, name)? {
+            Some(address) => address,
+            None => return Ok(None),
+        };
+        for section in file.sections() {
+            if address < section.address() || address >= section.address() + section.size() { continue; }
+            let data = section.data().map_err(|e| format!("unable to read {:?}: {}", path, e))?;
+            let data = &data[(address - section.address()) as usize..];
+            let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+            return Ok(Some(String::from_utf8_lossy(&data[..end]).into_owned()));
+        }
+        Err(format!("{} in {:?} isn't in any section", name, path))
+    })
+}
+
+/// Where an exported symbol is, if it is.
+fn address_of(path: &Path, file: &object::File, name: &str) -> Result<Option<u64>, String> {
+    if let object::File::Pe64(_) | object::File::Pe32(_) = *file {
+        let exports = file.exports().map_err(|e| format!("bad export table in {:?}: {}", path, e))?;
+        return Ok(exports.iter().find(|e| e.name() == name.as_bytes()).map(|e| e.address()));
+    }
+    Ok(file
+        .dynamic_symbols()
+        .find(|s| !s.is_undefined() && s.name() == Ok(name))
+        .map(|s| s.address()))
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub kind: SectionKind,
+    /// Bytes taken up in the file, which is nothing for `.bss`.
+    pub file_size: u64,
+}
+
+pub fn sections(path: &Path) -> Result<Vec<Section>, String> {
+    parse(path, |file| {
+        Ok(file.sections().map(|s| Section {
+            kind: s.kind(),
+            file_size: s.file_range().map(|(_, size)| size).unwrap_or(0),
+        }).collect())
+    })
+}
+
+/// Gives the path of a Rust symbol without its hash, or the name unchanged if it isn't one.
+pub fn demangle(name: &str) -> String {
+    match rustc_demangle::try_demangle(name) {
+        Ok(d) => format!("{:#}", d),
+        Err(_) => name.to_string(),
+    }
+}
+
+/// The crate a symbol came from, going by its demangled path.
+///
+/// Trait impls are attributed to the crate of the implementing type, so
+/// `<plugin::PluginSayHello as header::SayHelloService>::say_hello` belongs to `plugin`. Blanket
+/// impls, like `<T as alloc::string::ToString>::to_string`, go to the trait's crate. Generic code
+/// whose type parameters are from outside std goes to their crate, which instantiated it:
+/// `core::ptr::drop_in_place<plugin::PluginSayHello>` is `plugin`'s too.
+/// Returns `None` for non-Rust symbols.
+pub fn crate_of(name: &str) -> Option<String> {
+    if rustc_demangle::try_demangle(name).is_err() {
+        return None;
+    }
+    let path = demangle(name);
+    if let Some(krate) = argument_crates(&path).into_iter().find(|c| !matches!(&**c, "std" | "core" | "alloc")) {
+        return Some(krate);
+    }
+    if let Some(qualified) = path.strip_prefix('<') {
+        let (this, trait_) = match qualified.find(" as ") {
+            Some(i) => (&qualified[..i], Some(&qualified[i + " as ".len()..])),
+            None => (qualified, None),
+        };
+        return leading_crate(this).or_else(|| trait_.and_then(leading_crate));
+    }
+    leading_crate(&path)
+}
+
+/// The crates of the paths in a path's generic arguments, like `plugin` in
+/// `core::ptr::drop_in_place<plugin::PluginSayHello>`. Not the `<T as Trait>` of a trait impl.
+fn argument_crates(path: &str) -> Vec<String> {
+    let mut ret = vec![];
+    // For each `<` that's still open, whether it starts generic arguments.
+    let mut open = vec![];
+    let mut prev = ' ';
+    for (i, c) in path.char_indices() {
+        match c {
+            '<' => open.push(prev.is_alphanumeric() || prev == '_'),
+            '>' if prev != '-' => {
+                open.pop();
+            },
+            _ if open.contains(&true) && !(prev.is_alphanumeric() || prev == '_' || prev == ':') => {
+                ret.extend(leading_crate(&path[i..]));
+            },
+            _ => (),
+        }
+        prev = c;
+    }
+    ret
+}
+
+/// The first segment of a path, if there's more path after it.
+fn leading_crate(path: &str) -> Option<String> {
+    let mut path = path.trim_start_matches(['&', '*', '(', '[']);
+    while let Some(rest) = ["mut ", "const ", "dyn "].iter().find_map(|kw| path.strip_prefix(kw)) {
+        path = rest;
+    }
+    let end = path.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(path.len());
+    if end == 0 || !path[end..].starts_with("::") {
+        return None;
+    }
+    Some(path[..end].to_string())
+}
+
+/// The system libraries that `dylibs` need (libc, libm, the dynamic loader, ...), and the ones
+/// those need in turn, found where the loader would look. Pass the dylib along with the
+/// non-system libs it needs, like std & header: those bring in most of them.
+#[cfg(target_os = "linux")]
+pub fn system_libs(dylibs: &[&Path]) -> Result<Vec<PathBuf>, String> {
+    let mut ret: Vec<PathBuf> = vec![];
+    let mut todo = vec![];
+    for dylib in dylibs {
+        todo.extend(host::needed(dylib)?);
+    }
+    while let Some(name) = todo.pop() {
+        let lib = match host::find_system(&name) {
+            Some(lib) => lib,
+            None => continue,
+        };
+        if ret.contains(&lib) { continue; }
+        todo.extend(host::needed(&lib)?);
+        ret.push(lib);
+    }
+    Ok(ret)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn system_libs(_: &[&Path]) -> Result<Vec<PathBuf>, String> {
+    Ok(vec![])
+}
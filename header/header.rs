@@ -1,5 +1,327 @@
-pub trait SayHelloService {
-    fn say_hello(&self);
+use std::ffi::CStr;
+use std::os::raw::c_char;
+
+service_trait! {
+    pub trait SayHelloService {
+        fn say_hello(&self);
+    }
+}
+
+/// A trait that plugins implement and the host calls through `dyn`. If the host and plugin
+/// disagree about its methods, the vtable is wrong, so each has a fingerprint to compare.
+pub trait Service {
+    /// The trait's name, for messages.
+    const NAME: &'static str;
+    /// A hash of how the trait's methods are spelled: their names, and the tokens of their
+    /// arguments & return types. It's not a layout: if a type they use changes size, or `Foo`
+    /// means something else on the other side, this stays the same.
+    const SIGNATURE: u64;
+}
+
+/// The services a plugin offers. Plugins fill it in from their `declare_plugin!` function,
+/// with `<dyn Trait>::register` (which `service_trait!` provides), and the host looks them up
+/// by trait & name.
+#[derive(Default)]
+pub struct Registrar {
+    factories: Vec<Factory>,
+    save: Option<fn() -> Vec<u8>>,
+    restore: Option<fn(&[u8])>,
+}
+
+struct Factory {
+    service: &'static str,
+    signature: u64,
+    name: String,
+    /// A `fn() -> Box<dyn Trait>`.
+    new: Box<dyn std::any::Any>,
+    /// `<dyn Trait>::call_by_name`, for the `CEntry`.
+    call: fn(&dyn std::any::Any, &str) -> bool,
+}
+
+impl Registrar {
+    pub fn new() -> Registrar {
+        Registrar::default()
+    }
+
+    /// Use `<dyn Trait>::register` instead. That isn't generic, so plugins don't each get their
+    /// own copy of this.
+    #[doc(hidden)]
+    pub fn add(
+        &mut self,
+        service: &'static str,
+        signature: u64,
+        name: &str,
+        new: Box<dyn std::any::Any>,
+        call: fn(&dyn std::any::Any, &str) -> bool,
+    ) {
+        self.factories.push(Factory { service, signature, name: name.to_string(), new, call });
+    }
+
+    /// The constructor for the `S` called `name`.
+    pub fn get<S: Service + ?Sized + 'static>(&self, name: &str) -> Result<fn() -> Box<S>, String> {
+        let factory = self
+            .factories
+            .iter()
+            .find(|f| f.service == S::NAME && f.name == name)
+            .ok_or_else(|| format!("there's no {} called {:?}", S::NAME, name))?;
+        if factory.signature != S::SIGNATURE {
+            return Err(format!(
+                "{:?} was built against a different {} (signature {:016x}; expected {:016x})",
+                name, S::NAME, factory.signature, S::SIGNATURE,
+            ));
+        }
+        factory
+            .new
+            .downcast_ref::<fn() -> Box<S>>()
+            .copied()
+            .ok_or_else(|| format!("{:?} isn't really a {}", name, S::NAME))
+    }
+
+    /// Gives the host something to call when it's about to unload the plugin, to reload it
+    /// after it's been rebuilt. Whatever this returns goes to the new build's `restore` hook.
+    pub fn set_save_state(&mut self, save: fn() -> Vec<u8>) {
+        self.save = Some(save);
+    }
+
+    /// Gives the host something to call with the old build's state, once it's reloaded the
+    /// plugin.
+    pub fn set_restore_state(&mut self, restore: fn(&[u8])) {
+        self.restore = Some(restore);
+    }
+
+    /// The plugin's state, if it has a save hook.
+    pub fn save_state(&self) -> Option<Vec<u8>> {
+        self.save.map(|save| save())
+    }
+
+    /// Hands the plugin the state from its last build. False if it has no restore hook.
+    pub fn restore_state(&self, state: &[u8]) -> bool {
+        match self.restore {
+            Some(restore) => {
+                restore(state);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// The names of every `S` registered, in the order they were.
+    pub fn names<S: Service + ?Sized>(&self) -> Vec<&str> {
+        self.factories
+            .iter()
+            .filter(|f| f.service == S::NAME)
+            .map(|f| &*f.name)
+            .collect()
+    }
+}
+
+/// What `declare_plugin!` exports, under the name `entry_symbol()`.
+#[repr(C)]
+pub struct Entry {
+    /// `ENTRY_VERSION`. This comes first so that it can be checked before trusting the rest.
+    pub version: u32,
+    pub register: fn(&mut Registrar),
+}
+
+/// Goes up whenever `Entry` changes. It's also in the symbol name, which `declare_plugin!`
+/// has to spell out; the assert beside it makes sure that gets changed too.
+pub const ENTRY_VERSION: u32 = 2;
+
+/// The symbol a plugin's entry point is exported as.
+pub fn entry_symbol() -> String {
+    format!("plugins3_v{}_register", ENTRY_VERSION)
+}
+
+/// What `declare_plugin!` also exports, as `C_ENTRY_SYMBOL`, for hosts that load the plugin
+/// into a namespace of its own with `dlmopen`. The plugin has its own std & header in there,
+/// so no Rust types can cross; the host names a service, by its `Service::NAME` & `SIGNATURE`,
+/// and the plugin does the rest. Strings are NUL-terminated UTF-8.
+#[repr(C)]
+pub struct CEntry {
+    /// `C_ENTRY_VERSION`
+    pub version: u32,
+    /// Writes the names of the plugin's implementations of `service` with that `SIGNATURE`, a
+    /// line each, into `buf`, as much as fits. Returns how long the whole thing is.
+    pub names: extern "C" fn(service: *const c_char, signature: u64, buf: *mut u8, len: usize) -> usize,
+    /// Makes the `service` called `name`, and calls its `method`, which has to be one that only
+    /// takes `&self`. Returns `C_OK`, `C_NO_SUCH_SERVICE`, `C_NO_SUCH_METHOD` or `C_PANICKED`.
+    pub call: extern "C" fn(service: *const c_char, signature: u64, name: *const c_char, method: *const c_char) -> i32,
+}
+
+pub const C_ENTRY_VERSION: u32 = 2;
+/// Like `ENTRY_VERSION`, this is spelled out again in `declare_plugin!`, and checked there.
+pub const C_ENTRY_SYMBOL: &str = "plugins3_c_v2";
+
+pub const C_OK: i32 = 0;
+pub const C_NO_SUCH_SERVICE: i32 = 1;
+pub const C_PANICKED: i32 = 2;
+pub const C_NO_SUCH_METHOD: i32 = 3;
+
+/// # Safety
+/// `service` has to be NUL-terminated, and `buf` has to have room for `len` bytes.
+#[doc(hidden)]
+pub unsafe fn c_names(register: fn(&mut Registrar), service: *const c_char, signature: u64, buf: *mut u8, len: usize) -> usize {
+    let service = CStr::from_ptr(service).to_bytes();
+    let mut registrar = Registrar::new();
+    register(&mut registrar);
+    let names = registrar
+        .factories
+        .iter()
+        .filter(|f| f.service.as_bytes() == service && f.signature == signature)
+        .map(|f| &*f.name)
+        .collect::<Vec<&str>>()
+        .join("\n");
+    let n = names.len().min(len);
+    if n > 0 {
+        std::ptr::copy_nonoverlapping(names.as_ptr(), buf, n);
+    }
+    names.len()
+}
+
+/// # Safety
+/// `service`, `name` and `method` have to be NUL-terminated.
+#[doc(hidden)]
+pub unsafe fn c_call(
+    register: fn(&mut Registrar),
+    service: *const c_char,
+    signature: u64,
+    name: *const c_char,
+    method: *const c_char,
+) -> i32 {
+    let (service, name, method) = (CStr::from_ptr(service), CStr::from_ptr(name), CStr::from_ptr(method));
+    // Unwinding into the host would be UB, and it's not even the same std.
+    std::panic::catch_unwind(|| {
+        let mut registrar = Registrar::new();
+        register(&mut registrar);
+        let factory = registrar.factories.iter().find(|f| {
+            f.service.as_bytes() == service.to_bytes() && f.signature == signature && f.name.as_bytes() == name.to_bytes()
+        });
+        let factory = match factory {
+            Some(factory) => factory,
+            None => return C_NO_SUCH_SERVICE,
+        };
+        match method.to_str() {
+            Ok(method) if (factory.call)(&*factory.new, method) => C_OK,
+            _ => C_NO_SUCH_METHOD,
+        }
+    })
+    .unwrap_or(C_PANICKED)
+}
+
+// `export_name` has to be a literal, so `declare_plugin!` spells these out. Change its names
+// along with these.
+const _: () = assert!(ENTRY_VERSION == 2 && C_ENTRY_VERSION == 2);
+
+/// Exports a plugin's registration function, for the host to find with `entry_symbol`, along
+/// with a `CEntry` for loading it into a namespace.
+///
+/// ```ignore
+/// header::declare_plugin!(register);
+///
+/// fn register(registrar: &mut header::Registrar) {
+///     <dyn SayHelloService>::register(registrar, "hello", new_service);
+/// }
+/// ```
+#[macro_export]
+macro_rules! declare_plugin {
+    ($register:expr) => {
+        #[export_name = "plugins3_v2_register"]
+        pub static PLUGIN_ENTRY: $crate::Entry = $crate::Entry {
+            version: $crate::ENTRY_VERSION,
+            register: $register,
+        };
+
+        #[export_name = "plugins3_c_v2"]
+        pub static PLUGIN_C_ENTRY: $crate::CEntry = {
+            use std::os::raw::c_char;
+            extern "C" fn names(service: *const c_char, signature: u64, buf: *mut u8, len: usize) -> usize {
+                unsafe { $crate::c_names($register, service, signature, buf, len) }
+            }
+            extern "C" fn call(service: *const c_char, signature: u64, name: *const c_char, method: *const c_char) -> i32 {
+                unsafe { $crate::c_call($register, service, signature, name, method) }
+            }
+            $crate::CEntry {
+                version: $crate::C_ENTRY_VERSION,
+                names,
+                call,
+            }
+        };
+    };
+}
+
+/// FNV-1a of the trait's tokens, for `Service::SIGNATURE`.
+#[doc(hidden)]
+pub const fn signature_hash(s: &str) -> u64 {
+    let s = s.as_bytes();
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut i = 0;
+    while i < s.len() {
+        hash ^= s[i] as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    hash
+}
+
+/// Declares a trait for plugins to implement, implements `Service` for `dyn Trait`, and gives
+/// it a `<dyn Trait>::register` for plugins to offer implementations with.
+/// Methods can't have default bodies; they aren't part of the signature anyway.
+#[macro_export]
+macro_rules! service_trait {
+    (
+        $(#[$meta:meta])*
+        $vis:vis trait $name:ident {
+            $($(#[$m:meta])* fn $method:ident($($args:tt)*) $(-> $ret:ty)?;)*
+        }
+    ) => {
+        $(#[$meta])*
+        $vis trait $name {
+            $($(#[$m])* fn $method($($args)*) $(-> $ret)?;)*
+        }
+        impl $crate::Service for dyn $name {
+            const NAME: &'static str = stringify!($name);
+            const SIGNATURE: u64 = $crate::signature_hash(concat!(
+                stringify!($name),
+                $(" fn ", stringify!($method), stringify!(($($args)*)), $(" -> ", stringify!($ret),)? ";",)*
+            ));
+        }
+        impl dyn $name {
+            /// Offers an implementation to the host, under `name`.
+            pub fn register(registrar: &mut $crate::Registrar, name: &str, new: fn() -> Box<dyn $name>) {
+                registrar.add(
+                    <dyn $name as $crate::Service>::NAME,
+                    <dyn $name as $crate::Service>::SIGNATURE,
+                    name,
+                    Box::new(new),
+                    <dyn $name>::call_by_name,
+                );
+            }
+
+            /// Makes one with `new` (a `fn() -> Box<dyn Trait>`), and calls `method` on it. Only
+            /// methods that just take `&self`; false for anything else.
+            #[doc(hidden)]
+            pub fn call_by_name(new: &dyn std::any::Any, method: &str) -> bool {
+                let new = match new.downcast_ref::<fn() -> Box<dyn $name>>() {
+                    Some(new) => new,
+                    None => return false,
+                };
+                $($crate::__call_by_name!(new, method, $method($($args)*));)*
+                false
+            }
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __call_by_name {
+    ($new:ident, $method:ident, $name:ident(&self)) => {
+        if $method == stringify!($name) {
+            $new().$name();
+            return true;
+        }
+    };
+    ($new:ident, $method:ident, $name:ident($($args:tt)*)) => {};
 }
 
 pub fn greet() {
@@ -21,6 +343,101 @@ fn private_uncalled() {
 }
 
 
+/// `catch_unwind`, for the host. A plugin's panic can only be caught by the std that threw it,
+/// the dylib one, and the host needn't link that one (on Windows it has its own copy); so it
+/// looks this up in libheader instead.
+/// Gives the panic's message.
+#[no_mangle]
+pub fn plugins3_catch_panic(f: &mut dyn FnMut()) -> Option<String> {
+    let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).err()?;
+    Some(if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "(not a string)".into()
+    })
+}
+
 static mut GLOBAL: i32 = 0;
 pub fn get() -> i32 { unsafe { GLOBAL } }
 pub fn set(v: i32) { unsafe { GLOBAL = v; } }
+
+/// Exports one instantiation of a generic function from the header dylib, under a name of its
+/// own, so plugins can call that instead of compiling their own copy.
+///
+/// ```
+/// // What header does for `largest`:
+/// //     instantiate!(pub fn largest_i32 = largest::<i32>(items: &[i32]) -> i32);
+/// // and what a plugin calls, instead of `header::largest::<i32>`:
+/// assert_eq!(header::largest_i32(&[1, 3, 2]), 3);
+/// ```
+#[macro_export]
+macro_rules! instantiate {
+    ($vis:vis fn $name:ident = $f:ident::<$($t:ty),+>($($arg:ident: $ty:ty),*) $(-> $ret:ty)?) => {
+        #[inline(never)]
+        $vis fn $name($($arg: $ty),*) $(-> $ret)? {
+            $f::<$($t),+>($($arg),*)
+        }
+    };
+}
+
+/// The biggest of `items`, which mustn't be empty. Plugins should call one of the `largest_*`
+/// below rather than instantiating this themselves.
+pub fn largest<T: PartialOrd + Copy>(items: &[T]) -> T {
+    let mut ret = items[0];
+    for &i in items {
+        if i > ret { ret = i; }
+    }
+    ret
+}
+
+instantiate!(pub fn largest_i32 = largest::<i32>(items: &[i32]) -> i32);
+instantiate!(pub fn largest_f64 = largest::<f64>(items: &[f64]) -> f64);
+
+/// Declares functions that the host app provides to plugins. Plugins call them like any other
+/// `header` function; the host hands header a `HostFunctions` with one of each, before it loads
+/// any plugins, eg
+///
+/// ```ignore
+/// fn app_name() -> String { "app".into() }
+/// header::set_host_functions(header::HostFunctions { app_name });
+/// ```
+///
+/// A table rather than symbols for the host to define: libheader then links without the host,
+/// so anything can load it (or link it, like a test exe). Calling one before the host has set
+/// them panics.
+#[macro_export]
+macro_rules! host_functions {
+    ($($(#[$meta:meta])* $vis:vis fn $name:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)?;)*) => {
+        /// The host's implementations of the `host_functions!`.
+        #[derive(Clone, Copy)]
+        pub struct HostFunctions {
+            $(pub $name: fn($($ty),*) $(-> $ret)?,)*
+        }
+
+        static HOST_FUNCTIONS: std::sync::OnceLock<HostFunctions> = std::sync::OnceLock::new();
+
+        /// Only the first call counts. Exported under a fixed name for hosts that have to look it
+        /// up, like one loading header into a namespace of its own.
+        #[export_name = "plugins3_set_host_functions"]
+        pub fn set_host_functions(host: HostFunctions) {
+            let _ = HOST_FUNCTIONS.set(host);
+        }
+
+        $(
+            $(#[$meta])*
+            $vis fn $name($($arg: $ty),*) $(-> $ret)? {
+                let host = HOST_FUNCTIONS.get().unwrap_or_else(|| {
+                    panic!("header::{} was called before the host set its host functions", stringify!($name))
+                });
+                (host.$name)($($arg),*)
+            }
+        )*
+    };
+}
+
+host_functions! {
+    /// The name of the program that loaded the plugin.
+    pub fn app_name() -> String;
+}
@@ -0,0 +1,20 @@
+// A plugin whose service panics, until it's rebuilt with `--cfg fixed`.
+
+use header::SayHelloService;
+
+header::declare_plugin!(register);
+
+fn register(registrar: &mut header::Registrar) {
+    <dyn SayHelloService>::register(registrar, "panicky", || Box::new(Panicky));
+    // Once it's panicked, this shouldn't be asked for.
+    registrar.set_save_state(|| panic!("asked a disabled plugin for its state"));
+}
+
+struct Panicky;
+
+impl SayHelloService for Panicky {
+    fn say_hello(&self) {
+        #[cfg(not(fixed))]
+        panic!("not fixed yet");
+    }
+}
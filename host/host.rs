@@ -0,0 +1,828 @@
+//! Loading plugins and the interface libs they need, and getting services out of them.
+//!
+//! ```ignore
+//! let mut host = PluginHost::new();
+//! for (path, why) in host.load_dirs(&[dir])? {
+//!     println!("skipped {}: {}", path.display(), why);
+//! }
+//! let service = host.create::<dyn SayHelloService>("hello")?;
+//! service.call(|s| s.say_hello())?;
+//! ```
+
+#[cfg(target_os = "linux")]
+pub mod namespace;
+
+use header::{Registrar, Service};
+use libloading::Library;
+use object::{Object, ObjectSection};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::mem::ManuallyDrop;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+/// The section a dylib's manifest is in. (PE section names can't be longer than 8 bytes.)
+pub const MANIFEST_SECTION: &str = ".plugin";
+
+/// What a dylib says about itself. The builder writes it from the crate's Cargo metadata, so
+/// that the host can find out what a plugin is & needs without loading it.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    /// The other dylibs it needs loaded first, by name.
+    pub dependencies: Vec<String>,
+    /// The `header::ENTRY_VERSION` it was built with.
+    pub entry: u32,
+    /// Whether it's something plugins link against, like header, rather than a plugin.
+    pub interface: bool,
+}
+
+impl Manifest {
+    pub fn encode(&self) -> String {
+        format!(
+            "name={}\nversion={}\ndescription={}\ndependencies={}\nentry={}\ninterface={}\n",
+            self.name,
+            self.version,
+            self.description.replace('\n', " "),
+            self.dependencies.join(","),
+            self.entry,
+            self.interface,
+        )
+    }
+
+    pub fn decode(text: &str) -> Result<Manifest, String> {
+        let mut ret = Manifest::default();
+        for line in text.lines() {
+            let (k, v) = line.split_once('=').ok_or_else(|| format!("bad manifest line {:?}", line))?;
+            match k {
+                "name" => ret.name = v.to_string(),
+                "version" => ret.version = v.to_string(),
+                "description" => ret.description = v.to_string(),
+                "dependencies" => ret.dependencies = v.split(',').filter(|d| !d.is_empty()).map(String::from).collect(),
+                "entry" => ret.entry = v.parse().map_err(|_| format!("bad entry version {:?}", v))?,
+                "interface" => ret.interface = v == "true",
+                _ => (),
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Reads the manifest out of a dylib, without loading it. `None` if it hasn't got one.
+    pub fn read(path: &Path) -> Result<Option<Manifest>, String> {
+        let data = std::fs::read(path).map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+        let file = object::File::parse(&*data).map_err(|e| format!("unable to parse {}: {}", path.display(), e))?;
+        let section = match file.section_by_name(MANIFEST_SECTION) {
+            Some(section) => section,
+            None => return Ok(None),
+        };
+        let data = section.data().map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+        let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        Manifest::decode(&String::from_utf8_lossy(&data[..end])).map(Some)
+    }
+}
+
+/// The dylibs a dylib needs: its `DT_NEEDED` entries, or the DLLs in its import directory.
+pub fn needed(path: &Path) -> Result<Vec<String>, String> {
+    use object::read::elf::{Dyn, FileHeader, SectionHeader};
+    let data = std::fs::read(path).map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+    let bad = |e: object::Error| format!("unable to parse {}: {}", path.display(), e);
+    let mut ret = vec![];
+    match object::FileKind::parse(&*data).map_err(bad)? {
+        object::FileKind::Elf64 => {
+            let elf = object::elf::FileHeader64::<object::Endianness>::parse(&*data).map_err(bad)?;
+            let endian = elf.endian().map_err(bad)?;
+            let sections = elf.sections(endian, &*data).map_err(bad)?;
+            for section in sections.iter() {
+                let (dynamic, link) = match section.dynamic(endian, &*data).map_err(bad)? {
+                    Some(d) => d,
+                    None => continue,
+                };
+                let strings = sections.strings(endian, &*data, link).map_err(bad)?;
+                for d in dynamic {
+                    if d.tag32(endian) != Some(object::elf::DT_NEEDED) { continue; }
+                    let name = d.string(endian, strings).map_err(bad)?;
+                    ret.push(String::from_utf8_lossy(name).into_owned());
+                }
+            }
+        },
+        _ => {
+            let file = object::File::parse(&*data).map_err(bad)?;
+            for import in file.imports().map_err(bad)? {
+                let name = String::from_utf8_lossy(import.library()).into_owned();
+                if !ret.contains(&name) {
+                    ret.push(name);
+                }
+            }
+        },
+    }
+    Ok(ret)
+}
+
+/// Whether a dylib that isn't in the search paths is one the system's loader will find.
+fn is_system(name: &str) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        find_system(name).is_some()
+    }
+    #[cfg(target_os = "windows")]
+    {
+        let lower = name.to_ascii_lowercase();
+        if lower.starts_with("api-ms-") || lower.starts_with("ext-ms-") || lower.starts_with("vcruntime") {
+            return true;
+        }
+        let root = std::env::var_os("SystemRoot").unwrap_or_else(|| "C:\\Windows".into());
+        Path::new(&root).join("System32").join(name).exists()
+    }
+}
+
+/// Where the system's loader would find a dylib, going by the usual system dirs.
+#[cfg(target_os = "linux")]
+pub fn find_system(name: &str) -> Option<PathBuf> {
+    static SYSTEM_DIRS: &[&str] = &[
+        "/lib",
+        "/lib64",
+        "/usr/lib",
+        "/usr/lib64",
+        "/lib/x86_64-linux-gnu",
+        "/usr/lib/x86_64-linux-gnu",
+    ];
+    SYSTEM_DIRS.iter().map(|d| Path::new(d).join(name)).find(|p| p.exists())
+}
+
+/// What `PluginHost::plan` decided to do with the dylibs it found.
+#[derive(Debug, Default)]
+pub struct Plan {
+    /// What the plugins link against, in the order to load them: each after its dependencies.
+    pub interfaces: Vec<PathBuf>,
+    /// The plugins, in the order to load them.
+    pub load: Vec<(PathBuf, Manifest)>,
+    /// And why not.
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+/// A panic in a plugin, caught at the boundary. The plugin gets disabled.
+#[derive(Debug, Clone)]
+pub struct PluginError {
+    /// Its name from its manifest, or its file name.
+    pub plugin: String,
+    /// What it panicked with.
+    pub message: String,
+    /// Whether that was earlier, and this time it wasn't called, since it's disabled.
+    pub earlier: bool,
+}
+
+impl std::fmt::Display for PluginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.earlier {
+            write!(f, "plugin {} is disabled, since it panicked: {}", self.plugin, self.message)
+        } else {
+            write!(f, "plugin {} panicked: {}", self.plugin, self.message)
+        }
+    }
+}
+
+impl From<PluginError> for String {
+    fn from(e: PluginError) -> String {
+        e.to_string()
+    }
+}
+
+/// `header::plugins3_catch_panic`, from the loaded libheader.
+type Catch = fn(&mut dyn FnMut()) -> Option<String>;
+
+/// Runs some of a plugin's code, catching it if it panics. Unwinding out into the host would
+/// take the whole app down.
+fn contain<R>(catch: Option<Catch>, plugin: &str, f: impl FnOnce() -> R) -> Result<R, PluginError> {
+    let mut f = Some(f);
+    let mut ret = None;
+    let panic = {
+        let mut call = || ret = f.take().map(|f| f());
+        match catch {
+            Some(catch) => catch(&mut call),
+            // Only any good if the plugin's std is ours, eg if it's statically linked.
+            None => std::panic::catch_unwind(AssertUnwindSafe(call)).err().map(|payload| {
+                if let Some(s) = payload.downcast_ref::<&str>() {
+                    s.to_string()
+                } else if let Some(s) = payload.downcast_ref::<String>() {
+                    s.clone()
+                } else {
+                    "(not a string)".into()
+                }
+            }),
+        }
+    };
+    match (panic, ret) {
+        (None, Some(ret)) => Ok(ret),
+        (panic, _) => Err(PluginError {
+            plugin: plugin.to_string(),
+            message: panic.unwrap_or_else(|| "(it didn't return)".into()),
+            earlier: false,
+        }),
+    }
+}
+
+/// A service from a plugin. The plugin stays loaded for as long as this is around.
+pub struct ServiceHandle<S: ?Sized> {
+    // Dropped by hand, first, so that a panic in its `Drop` can be caught. The plugin's code
+    // has to still be there for that.
+    service: ManuallyDrop<Box<S>>,
+    guard: Guard,
+}
+
+impl<S: ?Sized> ServiceHandle<S> {
+    /// Calls into the service. If it panics, the plugin's disabled, and nothing else from it
+    /// gets called.
+    pub fn call<R>(&self, f: impl FnOnce(&S) -> R) -> Result<R, PluginError> {
+        self.guard.contain(|| f(&self.service))
+    }
+}
+
+impl<S: ?Sized> Drop for ServiceHandle<S> {
+    fn drop(&mut self) {
+        let service = unsafe { ManuallyDrop::take(&mut self.service) };
+        // Its Drop is the plugin's code too. It gets run even if the plugin's disabled, since
+        // there's no other way to be rid of it.
+        let (catch, name) = {
+            let live = self.guard.live.borrow();
+            (live.catch, live.name.clone())
+        };
+        if let Err(e) = contain(catch, &name, move || drop(service)) {
+            println!("warning: {}", e);
+            self.guard.live.borrow_mut().disabled.get_or_insert(e);
+        }
+    }
+}
+
+/// What the host and the objects from a plugin share: what's still around, by what it is (eg
+/// `SayHelloService "hello"`), and whether the plugin's been disabled for panicking.
+#[derive(Default)]
+struct Live {
+    name: String,
+    catch: Option<Catch>,
+    next: u64,
+    objects: BTreeMap<u64, String>,
+    disabled: Option<PluginError>,
+}
+
+impl Live {
+    /// Like `contain`, but the first panic disables the plugin, and after that it isn't called
+    /// at all.
+    fn contain<R>(live: &RefCell<Live>, f: impl FnOnce() -> R) -> Result<R, PluginError> {
+        let (catch, name) = {
+            let live = live.borrow();
+            if let Some(e) = &live.disabled {
+                return Err(PluginError { earlier: true, ..e.clone() });
+            }
+            (live.catch, live.name.clone())
+        };
+        contain(catch, &name, f).inspect_err(|e| {
+            live.borrow_mut().disabled = Some(e.clone());
+        })
+    }
+}
+
+/// Keeps a plugin loaded, and its entry in `Live` there, until the object it came with is
+/// dropped.
+struct Guard {
+    id: u64,
+    live: Rc<RefCell<Live>>,
+    _lib: Rc<Library>,
+}
+
+impl Guard {
+    fn contain<R>(&self, f: impl FnOnce() -> R) -> Result<R, PluginError> {
+        Live::contain(&self.live, f)
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.live.borrow_mut().objects.remove(&self.id);
+    }
+}
+
+pub struct Plugin {
+    path: PathBuf,
+    manifest: Option<Manifest>,
+    /// The file's, when it was loaded, to tell when it's been rebuilt.
+    modified: Option<SystemTime>,
+    services: Registrar,
+    live: Rc<RefCell<Live>>,
+    lib: Rc<Library>,
+}
+
+impl Plugin {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn manifest(&self) -> Option<&Manifest> {
+        self.manifest.as_ref()
+    }
+    pub fn library(&self) -> &Library {
+        &self.lib
+    }
+    pub fn services(&self) -> &Registrar {
+        &self.services
+    }
+    /// What it's made that's still around.
+    pub fn live(&self) -> Vec<String> {
+        self.live.borrow().objects.values().cloned().collect()
+    }
+    /// Its name from its manifest, or its file name.
+    pub fn name(&self) -> String {
+        self.live.borrow().name.clone()
+    }
+    /// Why it's been disabled, if it has.
+    pub fn disabled(&self) -> Option<PluginError> {
+        self.live.borrow().disabled.clone()
+    }
+}
+
+struct Interface {
+    path: PathBuf,
+    manifest: Option<Manifest>,
+    lib: Rc<Library>,
+}
+
+/// How plugins get opened. Interface libs are always global, since the plugins link against
+/// them; plugins never are, so nothing of theirs ends up in the global scope where it could
+/// override another library's symbols. interposition/ has tests of what each mode does.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum PluginMode {
+    /// `RTLD_LOCAL | RTLD_NOW`. A plugin's own exports can still be overridden by what's
+    /// global: if header and a plugin both export `my_guy`, the plugin calls header's.
+    #[default]
+    Local,
+    /// `RTLD_LOCAL | RTLD_NOW | RTLD_DEEPBIND`. A plugin's own symbols come before the global
+    /// ones. Same as `Local` on Windows, where DLLs already work like this.
+    DeepBind,
+}
+
+impl PluginMode {
+    pub fn from_name(name: &str) -> Option<PluginMode> {
+        match name {
+            "local" => Some(PluginMode::Local),
+            "deepbind" => Some(PluginMode::DeepBind),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PluginHost {
+    // Plugins first, so that they're unloaded before what they link against.
+    plugins: Vec<Plugin>,
+    interfaces: Vec<Interface>,
+    /// Where to look for the dylibs that plugins need, besides the plugin directories.
+    search_paths: Vec<PathBuf>,
+    mode: PluginMode,
+    catch: Option<Catch>,
+    /// For naming the copies that reloaded plugins are loaded from.
+    reloads: u32,
+}
+
+impl PluginHost {
+    pub fn new() -> PluginHost {
+        PluginHost::default()
+    }
+
+    /// How plugins loaded from now on get opened.
+    pub fn set_mode(&mut self, mode: PluginMode) {
+        self.mode = mode;
+    }
+
+    /// Loads a lib that plugins link against, such as std or header. Its symbols are made
+    /// available to everything loaded after it, so these have to come first.
+    pub fn load_interface(&mut self, path: &Path) -> Result<Rc<Library>, String> {
+        let lib = open_global(path).map_err(|e| format!("unable to load {}: {}", path.display(), e))?;
+        if let Ok(catch) = unsafe { lib.get::<Catch>(b"plugins3_catch_panic") } {
+            self.catch = Some(*catch);
+        }
+        let lib = Rc::new(lib);
+        self.interfaces.push(Interface {
+            path: path.to_owned(),
+            manifest: Manifest::read(path)?,
+            lib: lib.clone(),
+        });
+        Ok(lib)
+    }
+
+    /// A loaded interface lib, by the name in its manifest.
+    pub fn interface(&self, name: &str) -> Option<(&Path, Rc<Library>)> {
+        self.interfaces
+            .iter()
+            .find(|i| i.manifest.as_ref().map(|m| &*m.name) == Some(name))
+            .map(|i| (&*i.path, i.lib.clone()))
+    }
+
+    pub fn add_search_path(&mut self, dir: &Path) {
+        self.search_paths.push(dir.to_owned());
+    }
+
+    /// The dylibs in the directories that haven't been loaded as interface libs.
+    pub fn scan(&self, dirs: &[&Path]) -> Result<Vec<PathBuf>, String> {
+        let mut ret = vec![];
+        for dir in dirs {
+            let entries = std::fs::read_dir(dir).map_err(|e| format!("unable to read {}: {}", dir.display(), e))?;
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) != Some(DYLIB_EXTENSION) { continue; }
+                let name = path.file_name();
+                if self.interfaces.iter().any(|i| i.path.file_name() == name) { continue; }
+                ret.push(path);
+            }
+        }
+        ret.sort();
+        Ok(ret)
+    }
+
+    /// Finds the dylib called `name`: in the plugin directories if it's there, otherwise in the
+    /// search paths. `None` if it's been loaded already, or if it's a system library. The errors
+    /// read as the end of "x needs ...".
+    fn find(&self, name: &str, search: &[Vec<PathBuf>]) -> Result<Option<PathBuf>, String> {
+        if self.interfaces.iter().any(|i| i.path.file_name() == Some(name.as_ref())) {
+            return Ok(None);
+        }
+        for dirs in search {
+            let mut found: Vec<PathBuf> = vec![];
+            for dir in dirs {
+                let path = dir.join(name);
+                if !path.exists() { continue; }
+                let real = path.canonicalize().unwrap_or_else(|_| path.clone());
+                if found.iter().all(|f| f.canonicalize().unwrap_or_else(|_| f.clone()) != real) {
+                    found.push(path);
+                }
+            }
+            match found.len() {
+                0 => continue,
+                1 => return Ok(found.pop()),
+                _ => return Err(format!(
+                    "{}, and there's more than one: {}",
+                    name,
+                    found.iter().map(|d| d.display().to_string()).collect::<Vec<_>>().join(", "),
+                )),
+            }
+        }
+        if is_system(name) {
+            return Ok(None);
+        }
+        Err(format!(
+            "{}, which isn't in any of the search paths ({})",
+            name,
+            search.iter().flatten().map(|d| d.display().to_string()).collect::<Vec<_>>().join(", "),
+        ))
+    }
+
+    /// Puts `path` in `done` after everything it needs. `visiting` is the way here, to catch
+    /// cycles.
+    fn visit(&self, path: &Path, search: &[Vec<PathBuf>], visiting: &mut Vec<PathBuf>, done: &mut Vec<PathBuf>) -> Result<(), String> {
+        if done.iter().any(|d| d == path) {
+            return Ok(());
+        }
+        if let Some(i) = visiting.iter().position(|v| v == path) {
+            let cycle = visiting[i..].iter().map(|v| v.display().to_string()).collect::<Vec<_>>();
+            return Err(format!("{} needs itself, through {}", path.display(), cycle.join(" -> ")));
+        }
+        visiting.push(path.to_owned());
+        for name in needed(path)? {
+            let dep = self.find(&name, search).map_err(|e| format!("{} needs {}", path.display(), e))?;
+            if let Some(dep) = dep {
+                self.visit(&dep, search, visiting, done)?;
+            }
+        }
+        visiting.pop();
+        done.push(path.to_owned());
+        Ok(())
+    }
+
+    /// Works out which of the dylibs in the directories can be loaded, and in what order, from
+    /// their manifests and import tables. Nothing gets loaded.
+    pub fn plan(&self, dirs: &[&Path]) -> Result<Plan, String> {
+        let mut plan = Plan::default();
+        let search = [dirs.iter().map(|d| d.to_path_buf()).collect(), self.search_paths.clone()];
+        let mut candidates = vec![];
+        let mut others = vec![];
+        for path in self.scan(dirs)? {
+            match Manifest::read(&path)? {
+                Some(m) if m.interface => others.push(path),
+                Some(m) if m.entry != header::ENTRY_VERSION => {
+                    let why = format!("its entry point is version {}; the host knows version {}", m.entry, header::ENTRY_VERSION);
+                    plan.skipped.push((path, why));
+                },
+                Some(m) => candidates.push((path, m)),
+                None => others.push(path),
+            }
+        }
+        // Everything the plugins link against, in order.
+        let mut order = vec![];
+        candidates.retain(|(path, _)| {
+            let mut done = order.clone();
+            match self.visit(path, &search, &mut vec![], &mut done) {
+                Ok(()) => {
+                    order = done;
+                    true
+                },
+                Err(e) => {
+                    plan.skipped.push((path.clone(), e));
+                    false
+                },
+            }
+        });
+        let mut provided: HashSet<String> = self.interfaces.iter().filter_map(|i| i.manifest.as_ref()).map(|m| m.name.clone()).collect();
+        for path in &order {
+            if candidates.iter().any(|(c, _)| c == path) { continue; }
+            if let Some(m) = Manifest::read(path)? {
+                provided.insert(m.name);
+            }
+            plan.interfaces.push(path.clone());
+        }
+        for path in others {
+            if !order.contains(&path) {
+                plan.skipped.push((path, "it isn't a plugin, and no plugin needs it".into()));
+            }
+        }
+        // Then the plugins: take whatever has all its dependencies loaded already, until nothing
+        // does.
+        loop {
+            let ready = candidates
+                .iter()
+                .position(|(_, m)| m.dependencies.iter().all(|d| provided.contains(d)));
+            let (path, m) = match ready {
+                Some(i) => candidates.remove(i),
+                None => break,
+            };
+            provided.insert(m.name.clone());
+            plan.load.push((path, m));
+        }
+        let names: HashSet<&str> = candidates.iter().map(|(_, m)| &*m.name).collect();
+        for (path, m) in &candidates {
+            let missing: Vec<&str> = m
+                .dependencies
+                .iter()
+                .map(|d| &**d)
+                .filter(|d| !provided.contains(*d))
+                .collect();
+            let why = if missing.iter().all(|d| names.contains(d)) {
+                format!("it needs {}, which can't be loaded either", missing.join(", "))
+            } else {
+                format!("it needs {}, which isn't there", missing.join(", "))
+            };
+            plan.skipped.push((path.clone(), why));
+        }
+        Ok(plan)
+    }
+
+    /// Loads what the plan says to. A plugin that fails to load (or panics while it's
+    /// registering) doesn't stop the rest; they're returned, with why.
+    pub fn load(&mut self, plan: &Plan) -> Result<Vec<(PathBuf, String)>, String> {
+        for path in &plan.interfaces {
+            self.load_interface(path)?;
+        }
+        let mut failed = vec![];
+        for (path, _) in &plan.load {
+            if let Err(e) = self.load_plugin(path) {
+                failed.push((path.clone(), e));
+            }
+        }
+        Ok(failed)
+    }
+
+    /// Loads every plugin in the directories that can be, along with what they need, and says
+    /// why not for the others.
+    pub fn load_dirs(&mut self, dirs: &[&Path]) -> Result<Vec<(PathBuf, String)>, String> {
+        let plan = self.plan(dirs)?;
+        let mut failed = self.load(&plan)?;
+        failed.extend(plan.skipped);
+        Ok(failed)
+    }
+
+    /// Loads one plugin, and has it register its services.
+    pub fn load_plugin(&mut self, path: &Path) -> Result<&Plugin, String> {
+        let plugin = self.open(path, path)?;
+        self.plugins.push(plugin);
+        Ok(self.plugins.last().unwrap())
+    }
+
+    /// Loads the plugin at `path` from the file at `from`, which is either the same or a copy.
+    fn open(&self, path: &Path, from: &Path) -> Result<Plugin, String> {
+        let modified = path.metadata().and_then(|m| m.modified()).ok();
+        let manifest = Manifest::read(path)?;
+        let name = match &manifest {
+            Some(m) => m.name.clone(),
+            None => path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+        };
+        let lib = open_plugin(from, self.mode).map_err(|e| format!("unable to load {}: {}", path.display(), e))?;
+        let services = contain(self.catch, &name, || unsafe { register(&lib) })?.map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Plugin {
+            path: path.to_owned(),
+            manifest,
+            modified,
+            services,
+            live: Rc::new(RefCell::new(Live { name, catch: self.catch, ..Live::default() })),
+            lib: Rc::new(lib),
+        })
+    }
+
+    /// The plugins whose files have changed since they were loaded. Ones that changed very
+    /// recently are left out, since they might still be being written.
+    pub fn changed(&self) -> Vec<PathBuf> {
+        let mut ret = vec![];
+        for p in &self.plugins {
+            let modified = match p.path.metadata().and_then(|m| m.modified()) {
+                Ok(modified) => modified,
+                Err(_) => continue,
+            };
+            let settled = modified.elapsed().map(|age| age > Duration::from_millis(500)).unwrap_or(false);
+            if Some(modified) != p.modified && settled {
+                ret.push(p.path.clone());
+            }
+        }
+        ret
+    }
+
+    /// Errors if anything the plugin made is still around.
+    fn check_idle(&self, plugin: &Plugin) -> Result<(), String> {
+        let live = plugin.live();
+        if live.is_empty() {
+            return Ok(());
+        }
+        Err(format!(
+            "{} can't be unloaded while these are still around: {}",
+            plugin.path.display(),
+            live.join(", "),
+        ))
+    }
+
+    /// Unloads a plugin, unless something it made is still around.
+    pub fn unload(&mut self, path: &Path) -> Result<(), String> {
+        let i = self
+            .plugins
+            .iter()
+            .position(|p| p.path == path)
+            .ok_or_else(|| format!("{} isn't loaded", path.display()))?;
+        self.check_idle(&self.plugins[i])?;
+        self.plugins.remove(i);
+        Ok(())
+    }
+
+    /// Loads the new build of a plugin and swaps it in for the old one, handing its state over
+    /// with the save & restore hooks it gave its `Registrar`. Refuses while anything it made is
+    /// still around. If the new build can't be loaded, the old one stays. Returns whether the
+    /// new build took the state. A disabled plugin's state isn't to be trusted, or even asked
+    /// for, so the new build of one starts afresh.
+    pub fn reload(&mut self, path: &Path) -> Result<bool, String> {
+        let i = self
+            .plugins
+            .iter()
+            .position(|p| p.path == path)
+            .ok_or_else(|| format!("{} isn't loaded", path.display()))?;
+        self.check_idle(&self.plugins[i])?;
+        let old = &self.plugins[i];
+        let disabled = old.live.borrow().disabled.is_some();
+        let state = if disabled { None } else { Live::contain(&old.live, || old.services.save_state())? };
+        // dlopen would hand back the old build, which is still loaded, so the new one is loaded
+        // from a copy. (It would anyway if the old one couldn't be unloaded, eg because it has
+        // thread-local destructors.)
+        // FIXME: On Windows the DLL is locked while loaded, so the first build should come from
+        // a copy too, or it can't be rebuilt.
+        self.reloads += 1;
+        let mut shadow = path.as_os_str().to_owned();
+        shadow.push(format!(".reload-{}", self.reloads));
+        let shadow = PathBuf::from(shadow);
+        std::fs::copy(path, &shadow).map_err(|e| format!("unable to copy {}: {}", path.display(), e))?;
+        let plugin = self.open(path, &shadow);
+        if cfg!(unix) {
+            // It stays mapped.
+            std::fs::remove_file(&shadow).ok();
+        }
+        let plugin = plugin.map_err(|e| format!("{} (the old build is still loaded)", e))?;
+        drop(std::mem::replace(&mut self.plugins[i], plugin));
+        let plugin = &self.plugins[i];
+        // It's kept even if that panics; disabled, so that it's still there to be reloaded.
+        let restored = match state {
+            Some(state) => Live::contain(&plugin.live, || plugin.services.restore_state(&state))?,
+            None => false,
+        };
+        Ok(restored)
+    }
+
+    pub fn plugins(&self) -> &[Plugin] {
+        &self.plugins
+    }
+
+    /// Every `S` on offer, as `(plugin, name)`. Disabled plugins' are left out.
+    pub fn services<S: Service + ?Sized>(&self) -> Vec<(&Path, &str)> {
+        let mut ret = vec![];
+        for p in &self.plugins {
+            if p.live.borrow().disabled.is_some() { continue; }
+            for name in p.services.names::<S>() {
+                ret.push((&*p.path, name));
+            }
+        }
+        ret
+    }
+
+    /// Makes the `S` called `name`. If more than one plugin has one, the first one loaded wins.
+    pub fn create<S: Service + ?Sized + 'static>(&self, name: &str) -> Result<ServiceHandle<S>, String> {
+        let plugin = self
+            .plugins
+            .iter()
+            .find(|p| p.services.names::<S>().contains(&name))
+            .ok_or_else(|| format!("no plugin has a {} called {:?}", S::NAME, name))?;
+        let new = plugin.services.get::<S>(name).map_err(|e| format!("{}: {}", plugin.path.display(), e))?;
+        let service = Live::contain(&plugin.live, new)?;
+        let id = {
+            let mut live = plugin.live.borrow_mut();
+            live.next += 1;
+            let id = live.next;
+            live.objects.insert(id, format!("{} {:?}", S::NAME, name));
+            id
+        };
+        Ok(ServiceHandle {
+            service: ManuallyDrop::new(service),
+            guard: Guard {
+                id,
+                live: plugin.live.clone(),
+                _lib: plugin.lib.clone(),
+            },
+        })
+    }
+}
+
+impl Drop for PluginHost {
+    fn drop(&mut self) {
+        // The plugins stay loaded for whatever's left, but it's probably a mistake.
+        if cfg!(debug_assertions) {
+            for p in &self.plugins {
+                for object in p.live() {
+                    println!("leak: {} from {} is still around at shutdown", object, p.path.display());
+                }
+            }
+        }
+    }
+}
+
+/// Finds the plugin's `declare_plugin!` entry point, and has it register its services.
+unsafe fn register(lib: &Library) -> Result<Registrar, String> {
+    let name = header::entry_symbol();
+    let entry: libloading::Symbol<*const header::Entry> = lib
+        .get(name.as_bytes())
+        .map_err(|_| format!("no entry point (looked for {})", name))?;
+    let entry = &**entry;
+    if entry.version != header::ENTRY_VERSION {
+        return Err(format!(
+            "the entry point is version {}; the host knows version {}",
+            entry.version, header::ENTRY_VERSION,
+        ));
+    }
+    let mut registrar = Registrar::new();
+    (entry.register)(&mut registrar);
+    Ok(registrar)
+}
+
+#[cfg(target_os = "linux")]
+const DYLIB_EXTENSION: &str = "so";
+#[cfg(target_os = "windows")]
+const DYLIB_EXTENSION: &str = "dll";
+
+#[cfg(target_os = "linux")]
+fn open_global(path: &Path) -> Result<Library, libloading::Error> {
+    use libloading::os::unix::*;
+    // If the host links it itself (app links std & header), that's the copy to use: the loader
+    // goes by name, so the plugins would get that one whatever's loaded here.
+    if let Some(name) = path.file_name() {
+        if let Ok(lib) = unsafe { Library::open(Some(name), libc::RTLD_NOLOAD | RTLD_GLOBAL | RTLD_NOW) } {
+            return Ok(lib.into());
+        }
+    }
+    // Only std & the interface libs go here. Their symbols do override anything of the same
+    // name in a plugin, unless the plugin's loaded with PluginMode::DeepBind.
+    unsafe { Library::open(Some(path), RTLD_GLOBAL | RTLD_NOW).map(Into::into) }
+}
+
+#[cfg(target_os = "linux")]
+fn open_plugin(path: &Path, mode: PluginMode) -> Result<Library, libloading::Error> {
+    use libloading::os::unix::*;
+    let flags = match mode {
+        PluginMode::Local => RTLD_LOCAL | RTLD_NOW,
+        // It's a glibc extension, so libloading hasn't got it.
+        PluginMode::DeepBind => RTLD_LOCAL | RTLD_NOW | libc::RTLD_DEEPBIND,
+    };
+    unsafe { Library::open(Some(path), flags).map(Into::into) }
+}
+
+#[cfg(target_os = "windows")]
+fn open_global(path: &Path) -> Result<Library, libloading::Error> {
+    // NOTE: If running under wine, you may need to put vcruntime140d.dll by the .exe,
+    // if vcruntime isn't linked statically.
+    unsafe { Library::new(path) }
+}
+
+#[cfg(target_os = "windows")]
+fn open_plugin(path: &Path, _: PluginMode) -> Result<Library, libloading::Error> {
+    unsafe { Library::new(path) }
+}
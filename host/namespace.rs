@@ -0,0 +1,115 @@
+//! Loading a plugin into a link-map namespace of its own, with `dlmopen`.
+//!
+//! Everything the plugin links against gets loaded again in there: its own header, its own std,
+//! even its own libc. That's what lets two versions of a plugin, or plugins built against
+//! different headers, run side by side. It's also why the host can only talk to it through
+//! `header::CEntry`: a `String` or a `Box<dyn Trait>` from in there belongs to another allocator.
+//!
+//! That header needs its own `header::HostFunctions` too: the host sets them again in there.
+//!
+//! A panic in there is caught by the namespace's own std, and comes back as `C_PANICKED`.
+
+use header::{
+    CEntry, HostFunctions, Service, C_ENTRY_SYMBOL, C_ENTRY_VERSION, C_NO_SUCH_METHOD, C_NO_SUCH_SERVICE, C_OK,
+    C_PANICKED,
+};
+use libc::c_void;
+use std::ffi::{CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+pub struct Namespace {
+    path: PathBuf,
+    handle: *mut c_void,
+    entry: *const CEntry,
+}
+
+fn dlerror() -> String {
+    let e = unsafe { libc::dlerror() };
+    if e.is_null() {
+        return "unknown error".into();
+    }
+    unsafe { CStr::from_ptr(e) }.to_string_lossy().into_owned()
+}
+
+impl Namespace {
+    /// Makes a new namespace, loads the plugin into it, and gives the header in there `host`.
+    pub fn load(path: &Path, host: HostFunctions) -> Result<Namespace, String> {
+        let name = CString::new(path.as_os_str().as_bytes()).map_err(|_| format!("bad path {}", path.display()))?;
+        let handle = unsafe { libc::dlmopen(libc::LM_ID_NEWLM, name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            return Err(format!("unable to load {} into a namespace: {}", path.display(), dlerror()));
+        }
+        let mut ret = Namespace {
+            path: path.to_owned(),
+            handle,
+            entry: std::ptr::null(),
+        };
+        // Looked up through the plugin, which links that header.
+        let set = unsafe { libc::dlsym(ret.handle, b"plugins3_set_host_functions\0".as_ptr().cast()) };
+        if set.is_null() {
+            return Err(format!("{}'s header has no plugins3_set_host_functions", path.display()));
+        }
+        let set: fn(HostFunctions) = unsafe { std::mem::transmute(set) };
+        set(host);
+        let symbol = CString::new(C_ENTRY_SYMBOL).unwrap();
+        let entry = unsafe { libc::dlsym(ret.handle, symbol.as_ptr()) } as *const CEntry;
+        if entry.is_null() {
+            return Err(format!("{} has no {}", path.display(), C_ENTRY_SYMBOL));
+        }
+        let version = unsafe { (*entry).version };
+        if version != C_ENTRY_VERSION {
+            return Err(format!(
+                "{}'s {} is version {}; the host knows version {}",
+                path.display(), C_ENTRY_SYMBOL, version, C_ENTRY_VERSION,
+            ));
+        }
+        ret.entry = entry;
+        Ok(ret)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn entry(&self) -> &CEntry {
+        unsafe { &*self.entry }
+    }
+
+    /// The names of the plugin's `S`s.
+    pub fn names<S: Service + ?Sized>(&self) -> Vec<String> {
+        let service = CString::new(S::NAME).unwrap();
+        let mut buf = vec![0u8; 256];
+        loop {
+            let len = (self.entry().names)(service.as_ptr(), S::SIGNATURE, buf.as_mut_ptr(), buf.len());
+            if len <= buf.len() {
+                buf.truncate(len);
+                break;
+            }
+            buf.resize(len, 0);
+        }
+        let names = String::from_utf8_lossy(&buf);
+        names.lines().map(str::to_string).collect()
+    }
+
+    /// Has the plugin make the `S` called `name`, and call `method` on it. Only methods that
+    /// just take `&self`.
+    pub fn call<S: Service + ?Sized>(&self, name: &str, method: &str) -> Result<(), String> {
+        let service = CString::new(S::NAME).unwrap();
+        let c_name = CString::new(name).map_err(|_| format!("bad name {:?}", name))?;
+        let c_method = CString::new(method).map_err(|_| format!("bad method {:?}", method))?;
+        match (self.entry().call)(service.as_ptr(), S::SIGNATURE, c_name.as_ptr(), c_method.as_ptr()) {
+            C_OK => Ok(()),
+            C_NO_SUCH_SERVICE => Err(format!("{} has no {} called {:?}", self.path.display(), S::NAME, name)),
+            C_NO_SUCH_METHOD => Err(format!("{} has no method {}(&self)", S::NAME, method)),
+            C_PANICKED => Err(format!("{}'s {:?} panicked", self.path.display(), name)),
+            e => Err(format!("{}'s {:?} returned {}", self.path.display(), name, e)),
+        }
+    }
+}
+
+impl Drop for Namespace {
+    fn drop(&mut self) {
+        unsafe { libc::dlclose(self.handle) };
+    }
+}
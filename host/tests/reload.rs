@@ -0,0 +1,57 @@
+#![cfg(target_os = "linux")]
+
+use header::SayHelloService;
+use host::PluginHost;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Where cargo put this test, and the libheader.so it links.
+fn deps() -> PathBuf {
+    let exe = std::env::current_exe().expect("current_exe");
+    exe.parent().expect("test exe has no dir").to_owned()
+}
+
+/// Builds fixtures/panicky.rs against that libheader.so, to `out`. Into a new file, the way a
+/// real build would, rather than over the one that's loaded.
+fn build(out: &Path, cfg: &[&str]) {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let tmp = out.with_extension("tmp");
+    let status = Command::new(rustc)
+        .args(["--crate-type=dylib", "--edition=2018", "-C", "prefer-dynamic", "-o"])
+        .arg(&tmp)
+        .arg("--extern")
+        .arg(format!("header={}", deps().join("libheader.so").display()))
+        .arg("-L")
+        .arg(format!("dependency={}", deps().display()))
+        .args(cfg.iter().flat_map(|c| ["--cfg", c]))
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/panicky.rs"))
+        .status()
+        .expect("failed to run rustc");
+    assert!(status.success(), "failed to build the fixture");
+    std::fs::rename(&tmp, out).expect("rename the fixture");
+}
+
+#[test]
+fn reload_after_panic() {
+    let dir = std::env::temp_dir().join(format!("plugins3-reload-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("libpanicky.so");
+    build(&path, &[]);
+
+    let mut plugins = PluginHost::new();
+    plugins.load_interface(&deps().join("libheader.so")).unwrap();
+    plugins.load_plugin(&path).unwrap();
+    let said = plugins.create::<dyn SayHelloService>("panicky").unwrap().call(|s| s.say_hello());
+    assert!(said.is_err(), "the fixture didn't panic");
+    assert!(plugins.plugins()[0].disabled().is_some());
+
+    build(&path, &["fixed"]);
+    // No state: it's disabled, so it isn't asked for any.
+    assert_eq!(plugins.reload(&path), Ok(false));
+    assert!(plugins.plugins()[0].disabled().is_none());
+    let said = plugins.create::<dyn SayHelloService>("panicky").unwrap().call(|s| s.say_hello());
+    assert!(said.is_ok(), "the rebuilt fixture still panicked: {:?}", said.err());
+
+    drop(plugins);
+    std::fs::remove_dir_all(&dir).ok();
+}
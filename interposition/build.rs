@@ -0,0 +1,25 @@
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=fixtures");
+    println!("cargo:rerun-if-changed=tests/host.dynamic-list");
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("linux") {
+        return;
+    }
+    let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rustc-link-arg-tests=-Wl,--dynamic-list={}/tests/host.dynamic-list", dir);
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let out = std::env::var("OUT_DIR").expect("OUT_DIR");
+    for fixture in &["header", "plugin_a", "plugin_b"] {
+        let src = Path::new("fixtures").join(format!("{}.rs", fixture));
+        let dst = Path::new(&out).join(format!("lib{}.so", fixture));
+        let status = Command::new(&rustc)
+            .args(["--crate-type=cdylib", "--edition=2018", "-o"])
+            .arg(&dst)
+            .arg(&src)
+            .status()
+            .expect("failed to run rustc");
+        assert!(status.success(), "failed to build fixture {:?}", src);
+    }
+}
@@ -0,0 +1,13 @@
+// Stands in for an interface crate like `header`.
+
+const ME: i32 = 1;
+
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn header_value() -> i32 { ME }
+
+#[no_mangle]
+pub extern "C" fn header_calls_value() -> i32 { header_value() }
+
+#[no_mangle]
+pub extern "C" fn my_guy() -> i32 { ME }
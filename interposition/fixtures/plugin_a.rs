@@ -0,0 +1,21 @@
+// A plugin that exports a copy of everything: the header's function, and the same names that
+// plugin_b uses.
+
+const ME: i32 = 2;
+
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn header_value() -> i32 { ME }
+
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn plugin_value() -> i32 { ME }
+
+#[no_mangle]
+pub extern "C" fn my_guy() -> i32 { ME }
+
+#[no_mangle]
+pub extern "C" fn plugin_a_calls_plugin_value() -> i32 { plugin_value() }
+
+#[no_mangle]
+pub extern "C" fn plugin_a_calls_my_guy() -> i32 { my_guy() }
@@ -0,0 +1,23 @@
+// A plugin loaded after plugin_a, with the same names. It gets `header_value` from the header.
+
+const ME: i32 = 3;
+
+extern "C" {
+    fn header_value() -> i32;
+}
+
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn plugin_value() -> i32 { ME }
+
+#[no_mangle]
+pub extern "C" fn my_guy() -> i32 { ME }
+
+#[no_mangle]
+pub extern "C" fn plugin_b_calls_plugin_value() -> i32 { plugin_value() }
+
+#[no_mangle]
+pub extern "C" fn plugin_b_calls_my_guy() -> i32 { my_guy() }
+
+#[no_mangle]
+pub extern "C" fn plugin_b_calls_header_value() -> i32 { unsafe { header_value() } }
@@ -0,0 +1,109 @@
+//! Harness for checking whose symbols win once plugins are loaded.
+//!
+//! `build.rs` builds the dylibs in `./fixtures/`. Each one returns its own ID from every
+//! function, so calling through a library shows which copy of a symbol it's bound to.
+#![cfg(target_os = "linux")]
+
+use libloading::os::unix::*;
+use std::os::raw::c_int;
+use std::path::PathBuf;
+use std::process::Command;
+
+pub const HOST: i32 = 0;
+pub const HEADER: i32 = 1;
+pub const PLUGIN_A: i32 = 2;
+pub const PLUGIN_B: i32 = 3;
+
+/// The `dlopen` flags used for interface libs (std, header) and for plugins.
+#[derive(Debug, Copy, Clone)]
+pub struct Mode {
+    pub name: &'static str,
+    pub interface: c_int,
+    pub plugin: c_int,
+}
+
+pub const MODES: &[Mode] = &[
+    // `host::PluginMode::Local`, which is what app's main does.
+    Mode {
+        name: "local",
+        interface: RTLD_GLOBAL | RTLD_NOW,
+        plugin: RTLD_LOCAL | RTLD_NOW,
+    },
+    // What `Library::new` does, which is how plugins used to be loaded.
+    Mode {
+        name: "lazy",
+        interface: RTLD_GLOBAL | RTLD_NOW,
+        plugin: RTLD_LOCAL | RTLD_LAZY,
+    },
+    // `host::PluginMode::DeepBind`: a plugin looks in itself before the global scope.
+    Mode {
+        name: "deepbind",
+        interface: RTLD_GLOBAL | RTLD_NOW,
+        plugin: RTLD_LOCAL | RTLD_NOW | libc::RTLD_DEEPBIND,
+    },
+];
+
+pub struct Loaded {
+    pub mode: Mode,
+    pub header: Library,
+    pub plugin_a: Library,
+    pub plugin_b: Library,
+}
+
+impl Loaded {
+    pub fn load(mode: Mode) -> Loaded {
+        let open = |name: &str, flags| {
+            let path = fixture(name);
+            unsafe { Library::open(Some(&path), flags) }
+                .unwrap_or_else(|e| panic!("unable to load {:?}: {}", path, e))
+        };
+        Loaded {
+            mode,
+            header: open("header", mode.interface),
+            plugin_a: open("plugin_a", mode.plugin),
+            plugin_b: open("plugin_b", mode.plugin),
+        }
+    }
+}
+
+pub fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("OUT_DIR")).join(format!("lib{}.so", name))
+}
+
+/// Calls one of the fixtures' functions.
+pub fn call(lib: &Library, name: &str) -> i32 {
+    let f = unsafe { lib.get::<extern "C" fn() -> i32>(name.as_bytes()) }
+        .unwrap_or_else(|e| panic!("missing {:?}: {}", name, e));
+    f()
+}
+
+const MODE_VAR: &str = "INTERPOSITION_MODE";
+
+/// Runs `check` against the fixtures loaded in each of the `MODES`.
+///
+/// Libraries opened with `RTLD_GLOBAL` stick around for the rest of the process, so each mode
+/// gets its own process: the test binary runs itself again, with only `test` selected.
+pub fn each_mode(test: &str, check: impl Fn(&Loaded)) {
+    if let Ok(name) = std::env::var(MODE_VAR) {
+        let mode = MODES
+            .iter()
+            .find(|m| m.name == name)
+            .unwrap_or_else(|| panic!("unknown mode {:?}", name));
+        check(&Loaded::load(*mode));
+        return;
+    }
+    let exe = std::env::current_exe().expect("current_exe");
+    for mode in MODES {
+        let out = Command::new(&exe)
+            .args([test, "--exact", "--test-threads=1"])
+            .env(MODE_VAR, mode.name)
+            .output()
+            .expect("unable to run test binary");
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(
+            out.status.success() && stdout.contains("1 passed"),
+            "{} failed in mode {:?}:\n{}{}", test, mode.name, stdout, stderr,
+        );
+    }
+}
@@ -0,0 +1,69 @@
+#![cfg(target_os = "linux")]
+
+use interposition::*;
+use libloading::os::unix::Library;
+
+// Exported through tests/host.dynamic-list, so the plugins can see it.
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn my_guy() -> i32 { HOST }
+
+#[test]
+fn host_is_not_interposed() {
+    each_mode("host_is_not_interposed", |_| {
+        let global = Library::this();
+        assert!(unsafe { global.get::<extern "C" fn() -> i32>(b"my_guy") }.is_ok(), "the host doesn't export my_guy");
+        assert_eq!(my_guy(), HOST);
+    });
+}
+
+#[test]
+fn header_is_not_interposed() {
+    each_mode("header_is_not_interposed", |libs| {
+        assert_eq!(call(&libs.header, "header_value"), HEADER);
+        assert_eq!(call(&libs.header, "header_calls_value"), HEADER);
+        assert_eq!(call(&libs.header, "my_guy"), HEADER);
+        // plugin_a exports its own header_value, but plugin_b must still get the header's.
+        assert_eq!(call(&libs.plugin_b, "plugin_b_calls_header_value"), HEADER);
+    });
+}
+
+#[test]
+fn plugins_are_not_interposed_by_other_plugins() {
+    each_mode("plugins_are_not_interposed_by_other_plugins", |libs| {
+        assert_eq!(call(&libs.plugin_a, "plugin_a_calls_plugin_value"), PLUGIN_A);
+        assert_eq!(call(&libs.plugin_b, "plugin_b_calls_plugin_value"), PLUGIN_B);
+        assert_eq!(call(&libs.plugin_a, "plugin_value"), PLUGIN_A);
+        assert_eq!(call(&libs.plugin_b, "plugin_value"), PLUGIN_B);
+    });
+}
+
+#[test]
+fn plugins_stay_out_of_the_global_scope() {
+    each_mode("plugins_stay_out_of_the_global_scope", |_| {
+        let global = Library::this();
+        assert!(unsafe { global.get::<extern "C" fn() -> i32>(b"header_value") }.is_ok());
+        assert!(unsafe { global.get::<extern "C" fn() -> i32>(b"plugin_value") }.is_err());
+        assert!(unsafe { global.get::<extern "C" fn() -> i32>(b"plugin_a_calls_my_guy") }.is_err());
+    });
+}
+
+#[test]
+fn my_guy_overrides() {
+    each_mode("my_guy_overrides", |libs| {
+        let a = call(&libs.plugin_a, "plugin_a_calls_my_guy");
+        let b = call(&libs.plugin_b, "plugin_b_calls_my_guy");
+        // Still there, for anyone asking header for it.
+        assert_eq!(call(&libs.header, "my_guy"), HEADER);
+        match libs.mode.name {
+            // The global scope comes first, and the host is at the front of it, then header:
+            // whatever the host exports beats the plugins' own. That's why app exports nothing
+            // (header gets its host functions as a table), and why check_collisions counts it.
+            "local" | "lazy" => assert_eq!((a, b), (HOST, HOST)),
+            // The plugins look in themselves first.
+            "deepbind" => assert_eq!((a, b), (PLUGIN_A, PLUGIN_B)),
+            m => panic!("nothing expected for mode {:?}", m),
+        }
+    });
+}
+
@@ -1,29 +1,58 @@
 extern crate header;
 
 use header::SayHelloService;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-#[no_mangle]
-pub extern "Rust" fn new_service() -> Box<dyn SayHelloService> {
-    /*assert_eq!(header::get(), 1);
-    header::set(2);*/
+header::declare_plugin!(register);
+
+fn register(registrar: &mut header::Registrar) {
+    <dyn SayHelloService>::register(registrar, "hello", new_service);
+    <dyn SayHelloService>::register(registrar, "quiet", || Box::new(QuietSayHello));
+    registrar.set_save_state(|| HELLOS.load(Ordering::Relaxed).to_le_bytes().to_vec());
+    registrar.set_restore_state(|state| {
+        let mut n = [0; 8];
+        n.copy_from_slice(&state[..8]);
+        HELLOS.store(u64::from_le_bytes(n), Ordering::Relaxed);
+        println!("[plugin] Reloaded; said hello {} times before", u64::from_le_bytes(n));
+    });
+}
+
+/// How many times we've said hello, including in builds since reloaded.
+static HELLOS: AtomicU64 = AtomicU64::new(0);
+
+fn new_service() -> Box<dyn SayHelloService> {
+    assert_eq!(header::get(), 1);
+    header::set(2);
     Box::new(PluginSayHello::new())
 }
 
+/// Says hello without any fuss.
+pub struct QuietSayHello;
+
+impl SayHelloService for QuietSayHello {
+    fn say_hello(&self) {
+        HELLOS.fetch_add(1, Ordering::Relaxed);
+        println!("hello");
+    }
+}
+
 pub struct PluginSayHello {
     id: String,
 }
 
 impl PluginSayHello {
     fn new() -> PluginSayHello {
-        let id = format!("plugin");
-        println!("[{}] Created instance!", id);
+        let id = "plugin".to_string();
+        println!("[{}] Created instance in {}!", id, header::app_name());
         PluginSayHello { id }
     }
 }
 
 impl SayHelloService for PluginSayHello {
     fn say_hello(&self) {
+        HELLOS.fetch_add(1, Ordering::Relaxed);
         println!("[{}] Hello from plugin!", self.id);
+        println!("[{}] The largest of 1, 3 & 2 is {}", self.id, header::largest_i32(&[1, 3, 2]));
         header::greet();
     }
 }
@@ -33,9 +62,3 @@ impl Drop for PluginSayHello {
         println!("[{}] Destroyed instance!", self.id);
     }
 }
-
-
-#[no_mangle]
-fn my_guy() {
-    panic!("this is plugin's my_guy()");
-}